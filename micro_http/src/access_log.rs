@@ -0,0 +1,318 @@
+use std::io::Write;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use common::message::Message;
+use common::net::PeerInfo;
+use common::{Method, Version};
+use request::Request;
+use response::StatusCode;
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// The format of the lines written to an access log.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AccessLogFormat {
+    /// The Common Log Format, followed by the latency in microseconds:
+    ///
+    /// `127.0.0.1 - - [17/Oct/2026:13:55:36 +0000] "GET /vm HTTP/1.1" 200 38 154`
+    ///
+    /// The client of a Unix domain socket is logged by its user ID instead of its
+    /// address. Responses sent by the server on its own, as for requests which
+    /// failed to parse, are logged with `"-"` as the request.
+    Common,
+    /// One JSON object per line, with the fields `time`, `peer` or `pid`, `uid`
+    /// and `gid`, `method`, `uri`, `version`, `status`, `bytes` and `latency_us`.
+    /// The request fields are `null` for responses sent by the server on its own.
+    Json,
+}
+
+/// The parts of a request line recorded for the access log.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct RequestSummary {
+    pub method: Method,
+    pub uri: String,
+    pub version: Version,
+}
+
+impl RequestSummary {
+    pub fn new(request: &Request) -> Self {
+        Self {
+            method: request.method(),
+            uri: request.uri().as_str().to_string(),
+            version: request.version(),
+        }
+    }
+}
+
+/// A completely sent response, along with the request it answers.
+#[derive(Clone, Debug)]
+pub(crate) struct AccessLogEntry {
+    /// The client which sent the request.
+    pub peer: PeerInfo,
+    /// The request, or `None` for the responses sent by the server on its own.
+    pub request: Option<RequestSummary>,
+    /// The moment at which the request was received.
+    pub time: SystemTime,
+    /// The status code of the response.
+    pub status_code: StatusCode,
+    /// The number of bytes written for the response, its head included.
+    pub response_len: usize,
+    /// The time between receiving the request and sending the last byte of its
+    /// response.
+    pub latency: Duration,
+}
+
+/// An access log, to which a line is written for every response sent by the
+/// server.
+///
+/// Lines are written as a whole with `write_all`. Errors writing them are
+/// ignored, so that logging never gets in the way of serving requests.
+///
+/// # Example
+///
+/// ```
+/// extern crate micro_http;
+/// use micro_http::{AccessLog, AccessLogFormat, HttpServer};
+///
+/// let path_to_socket = "/tmp/example_access_log.sock";
+/// std::fs::remove_file(path_to_socket).unwrap_or_default();
+///
+/// let mut server = HttpServer::new_uds(path_to_socket).unwrap();
+/// server.set_access_log(Some(AccessLog::new(
+///     std::io::stderr(),
+///     AccessLogFormat::Common,
+/// )));
+/// server.start_server().unwrap();
+/// ```
+pub struct AccessLog {
+    sink: Box<dyn Write + Send>,
+    format: AccessLogFormat,
+}
+
+impl AccessLog {
+    /// Creates an access log which writes lines in `format` to `sink`.
+    pub fn new<W: Write + Send + 'static>(sink: W, format: AccessLogFormat) -> Self {
+        Self {
+            sink: Box::new(sink),
+            format,
+        }
+    }
+
+    /// Writes the line of `entry`.
+    pub(crate) fn log(&mut self, entry: &AccessLogEntry) {
+        let line = match self.format {
+            AccessLogFormat::Common => common_line(entry),
+            AccessLogFormat::Json => json_line(entry),
+        };
+        let _ = self.sink.write_all(line.as_bytes());
+    }
+}
+
+impl std::fmt::Debug for AccessLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("AccessLog")
+            .field("format", &self.format)
+            .finish()
+    }
+}
+
+/// Formats `entry` in the Common Log Format, followed by the latency.
+fn common_line(entry: &AccessLogEntry) -> String {
+    let (host, user) = match entry.peer {
+        PeerInfo::Tcp(addr) => (addr.ip().to_string(), "-".to_string()),
+        PeerInfo::Uds(credentials) => ("-".to_string(), credentials.uid.to_string()),
+    };
+    let (year, month, day, hour, minute, second) = utc_time(entry.time);
+    let request = match entry.request.as_ref() {
+        Some(request) => format!(
+            "{} {} {}",
+            String::from_utf8_lossy(request.method.raw()),
+            request.uri,
+            String::from_utf8_lossy(request.version.raw())
+        ),
+        None => "-".to_string(),
+    };
+    format!(
+        "{} - {} [{:02}/{}/{}:{:02}:{:02}:{:02} +0000] \"{}\" {} {} {}\n",
+        host,
+        user,
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        hour,
+        minute,
+        second,
+        request,
+        entry.status_code.code(),
+        entry.response_len,
+        entry.latency.as_micros()
+    )
+}
+
+/// Formats `entry` as a JSON object on a line of its own.
+fn json_line(entry: &AccessLogEntry) -> String {
+    let (year, month, day, hour, minute, second) = utc_time(entry.time);
+    let peer = match entry.peer {
+        PeerInfo::Tcp(addr) => format!("\"peer\":\"{}\"", addr),
+        PeerInfo::Uds(credentials) => format!(
+            "\"pid\":{},\"uid\":{},\"gid\":{}",
+            credentials.pid, credentials.uid, credentials.gid
+        ),
+    };
+    let request = match entry.request.as_ref() {
+        Some(request) => format!(
+            "\"method\":\"{}\",\"uri\":\"{}\",\"version\":\"{}\"",
+            json_escape(&String::from_utf8_lossy(request.method.raw())),
+            json_escape(&request.uri),
+            String::from_utf8_lossy(request.version.raw())
+        ),
+        None => "\"method\":null,\"uri\":null,\"version\":null".to_string(),
+    };
+    format!(
+        "{{\"time\":\"{}-{:02}-{:02}T{:02}:{:02}:{:02}Z\",{},{},\"status\":{},\"bytes\":{},\
+         \"latency_us\":{}}}\n",
+        year,
+        month,
+        day,
+        hour,
+        minute,
+        second,
+        peer,
+        request,
+        entry.status_code.code(),
+        entry.response_len,
+        entry.latency.as_micros()
+    )
+}
+
+/// Escapes the characters of `value` which can not appear as they are in a JSON
+/// string.
+fn json_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Splits `time` into its UTC year, month, day, hour, minute and second.
+fn utc_time(time: SystemTime) -> (i64, u32, u32, u64, u64, u64) {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+    let (hour, minute, second) = (secs / 3600 % 24, secs / 60 % 60, secs % 60);
+
+    // Converts the number of days since the epoch to a date of the proleptic
+    // Gregorian calendar, as described in http://howardhinnant.github.io/date_algorithms.html.
+    let days = (secs / 86400) as i64 + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day, hour, minute, second)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::net::PeerCredentials;
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+    fn entry(peer: PeerInfo, request: Option<RequestSummary>) -> AccessLogEntry {
+        AccessLogEntry {
+            peer,
+            request,
+            time: UNIX_EPOCH + Duration::from_secs(1_792_245_336),
+            status_code: StatusCode::OK,
+            response_len: 38,
+            latency: Duration::from_micros(154),
+        }
+    }
+
+    fn tcp_peer() -> PeerInfo {
+        PeerInfo::Tcp(SocketAddr::new(
+            IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+            8080,
+        ))
+    }
+
+    fn uds_peer() -> PeerInfo {
+        PeerInfo::Uds(PeerCredentials {
+            pid: 42,
+            uid: 1000,
+            gid: 100,
+        })
+    }
+
+    fn request(uri: &str) -> Option<RequestSummary> {
+        Some(RequestSummary {
+            method: Method::Get,
+            uri: uri.to_string(),
+            version: Version::Http11,
+        })
+    }
+
+    #[test]
+    fn test_common_line() {
+        assert_eq!(
+            common_line(&entry(tcp_peer(), request("/vm"))),
+            "127.0.0.1 - - [17/Oct/2026:13:55:36 +0000] \"GET /vm HTTP/1.1\" 200 38 154\n"
+        );
+        // The client of a Unix domain socket is logged by its user ID.
+        assert_eq!(
+            common_line(&entry(uds_peer(), None)),
+            "- - 1000 [17/Oct/2026:13:55:36 +0000] \"-\" 200 38 154\n"
+        );
+    }
+
+    #[test]
+    fn test_json_line() {
+        assert_eq!(
+            json_line(&entry(tcp_peer(), request("/vm?name=\"a\\b\"\t"))),
+            "{\"time\":\"2026-10-17T13:55:36Z\",\"peer\":\"127.0.0.1:8080\",\"method\":\"GET\",\
+             \"uri\":\"/vm?name=\\\"a\\\\b\\\"\\u0009\",\"version\":\"HTTP/1.1\",\"status\":200,\
+             \"bytes\":38,\"latency_us\":154}\n"
+        );
+        assert_eq!(
+            json_line(&entry(uds_peer(), None)),
+            "{\"time\":\"2026-10-17T13:55:36Z\",\"pid\":42,\"uid\":1000,\"gid\":100,\
+             \"method\":null,\"uri\":null,\"version\":null,\"status\":200,\"bytes\":38,\
+             \"latency_us\":154}\n"
+        );
+    }
+
+    #[test]
+    fn test_utc_time() {
+        let utc = |secs| utc_time(UNIX_EPOCH + Duration::from_secs(secs));
+        assert_eq!(utc(0), (1970, 1, 1, 0, 0, 0));
+        // Years divisible by 400 are leap years.
+        assert_eq!(utc(951_827_696), (2000, 2, 29, 12, 34, 56));
+        assert_eq!(utc(978_307_199), (2000, 12, 31, 23, 59, 59));
+        assert_eq!(utc(1_709_164_800), (2024, 2, 29, 0, 0, 0));
+        // Other years divisible by 100 are not.
+        assert_eq!(utc(4_107_542_399), (2100, 2, 28, 23, 59, 59));
+        assert_eq!(utc(4_107_542_400), (2100, 3, 1, 0, 0, 0));
+        // Times before the epoch are clamped to it.
+        assert_eq!(
+            utc_time(UNIX_EPOCH - Duration::from_secs(1)),
+            (1970, 1, 1, 0, 0, 0)
+        );
+    }
+}
@@ -22,7 +22,7 @@ impl<T: Read + Write> Client<T> {
         request
             .send(&mut self.socket)
             .map_err(|_| MessageError::IOError)?;
-        Ok(Response::receive(&mut self.socket)?)
+        Response::receive(&mut self.socket)
     }
 
     pub fn base_url(&self) -> &String {
@@ -7,17 +7,11 @@ use libc::{
     EPOLL_CTL_ADD, EPOLL_CTL_DEL, EPOLL_CTL_MOD,
 };
 
+use common::cvt;
+
 pub const EPOLL_IN: u32 = libc::EPOLLIN as u32;
 pub const EPOLL_OUT: u32 = libc::EPOLLOUT as u32;
 
-fn cvt(result: libc::c_int) -> io::Result<libc::c_int> {
-    if result < 0 {
-        Err(io::Error::last_os_error())
-    } else {
-        Ok(result)
-    }
-}
-
 #[repr(i32)]
 pub enum ControlOperation {
     /// Add a file descriptor to the interest list.
@@ -42,21 +36,17 @@ impl EventSet {
     }
 
     pub fn add(&mut self, flag: u32) -> &Self {
-        self.flags = self.flags | flag;
+        self.flags |= flag;
         self
     }
 
     pub fn remove(&mut self, flag: u32) -> &Self {
-        self.flags = self.flags ^ flag;
+        self.flags ^= flag;
         self
     }
 
     pub fn contains(&self, flag: u32) -> bool {
-        if self.flags & flag != 0 {
-            true
-        } else {
-            false
-        }
+        self.flags & flag != 0
     }
 
     pub fn bits(&self) -> u32 {
@@ -0,0 +1,46 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+
+use libc::{eventfd, EFD_CLOEXEC, EFD_NONBLOCK};
+
+use common::{clear_counter, cvt};
+
+/// Wrapper over a non-blocking `eventfd`, which becomes readable once it is
+/// notified from any thread.
+#[derive(Debug)]
+pub struct EventFd {
+    event_fd: File,
+}
+
+impl EventFd {
+    /// Create a new event file descriptor, which is not readable until notified.
+    pub fn new() -> io::Result<Self> {
+        // Safe because the call has no pointer arguments and we check the return value.
+        let event_fd = cvt(unsafe { eventfd(0, EFD_NONBLOCK | EFD_CLOEXEC) })?;
+        // Safe because the descriptor was just created and nothing else owns it.
+        let event_fd = unsafe { File::from_raw_fd(event_fd) };
+        Ok(EventFd { event_fd })
+    }
+
+    /// Makes the event file descriptor readable.
+    pub fn notify(&self) -> io::Result<()> {
+        match (&self.event_fd).write(&1u64.to_ne_bytes()) {
+            // The counter is saturated, so the descriptor is readable anyway.
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(()),
+            result => result.map(|_| ()),
+        }
+    }
+
+    /// Consumes the notifications, so that the event file descriptor is no
+    /// longer readable.
+    pub fn clear(&self) -> io::Result<()> {
+        clear_counter(&self.event_fd)
+    }
+}
+
+impl AsRawFd for EventFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.event_fd.as_raw_fd()
+    }
+}
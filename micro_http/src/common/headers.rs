@@ -5,7 +5,8 @@ use std::collections::HashMap;
 use std::io::{Error as WriteError, Write};
 use std::result::Result;
 
-use common::MessageError;
+use common::ascii::{COLON, CR, DEL, HTAB, LF, SP};
+use common::{is_token_char, MessageError, RequestError};
 
 /// Wrapper over an HTTP Header type.
 #[derive(Debug, Eq, Hash, PartialEq)]
@@ -68,24 +69,152 @@ impl Header {
 /// invalidate our request as we don't support the full set of HTTP/1.1 specification.
 /// Such header entries are "Transfer-Encoding: identity; q=0", which means a compression
 /// algorithm is applied to the body of the request, or "Expect: 103-checkpoint".
-#[derive(Debug, Default)]
+///
+/// Field names are matched case-insensitively. A field can appear multiple times
+/// and all its values are kept. The fields are written in the order in which
+/// they were added.
+///
+/// # Examples
+///
+/// ```
+/// extern crate micro_http;
+/// use micro_http::Headers;
+///
+/// let header_lines = b"Via: 1.0 first\r\nexpect: 100-continue\r\nVia: 1.1 second\r\n\r\n";
+/// let headers = Headers::try_from(header_lines).unwrap();
+/// assert_eq!(headers.header_line("Expect"), Some(&"100-continue".to_string()));
+/// assert_eq!(headers.header_line("via"), Some(&"1.0 first".to_string()));
+/// assert_eq!(headers.header_lines("VIA"), vec!["1.0 first", "1.1 second"]);
+///
+/// let mut buf = Vec::new();
+/// headers.write_all(&mut buf).unwrap();
+/// assert_eq!(&buf[..], &header_lines[..]);
+/// ```
+#[derive(Debug, Default, PartialEq)]
 pub struct Headers {
     /// The `Content-Length` header field tells us how many bytes we need to receive
     /// from the source after the headers.
     content_length: i32,
-    pub map: HashMap<String, String>,
+    /// Whether a `Content-Length` header field was parsed.
+    content_length_received: bool,
+    /// The `Transfer-Encoding: chunked` header field tells us that the body is sent
+    /// as a series of chunks, each prefixed by its size, instead of having a known length.
+    chunked: bool,
+    /// All the other header fields, in the order they were added.
+    entries: Vec<HeaderField>,
+}
+
+/// A single header field.
+#[derive(Debug, PartialEq)]
+struct HeaderField {
+    name: String,
+    /// The value of the field. If the raw value is not valid UTF-8, invalid
+    /// sequences are replaced with `U+FFFD`.
+    value: String,
+    /// The value of the field as it was received, kept only if it is not valid UTF-8.
+    raw_value: Option<Vec<u8>>,
+}
+
+impl HeaderField {
+    fn new(name: String, value: String) -> Self {
+        Self {
+            name,
+            value,
+            raw_value: None,
+        }
+    }
+
+    fn from_bytes(name: String, value: &[u8]) -> Self {
+        match String::from_utf8(value.to_vec()) {
+            Ok(value) => Self::new(name, value),
+            Err(_) => Self {
+                name,
+                value: String::from_utf8_lossy(value).into_owned(),
+                raw_value: Some(value.to_vec()),
+            },
+        }
+    }
+
+    fn value_bytes(&self) -> &[u8] {
+        match self.raw_value {
+            Some(ref raw_value) => raw_value,
+            None => self.value.as_bytes(),
+        }
+    }
+}
+
+/// Removes the optional whitespace surrounding a header field value.
+fn trim_ows(mut bytes: &[u8]) -> &[u8] {
+    while let Some((&first, rest)) = bytes.split_first() {
+        if first != SP && first != HTAB {
+            break;
+        }
+        bytes = rest;
+    }
+    while let Some((&last, rest)) = bytes.split_last() {
+        if last != SP && last != HTAB {
+            break;
+        }
+        bytes = rest;
+    }
+    bytes
+}
+
+/// Splits a header line of the format `field-name ":" OWS field-value OWS` into
+/// the name and the value of the field.
+///
+/// # Errors
+/// `MalformedHeader` is returned when the header line is not syntactically valid.
+fn split_header_line(header_line: &[u8]) -> Result<(&[u8], &[u8]), MessageError> {
+    // A line starting with whitespace is the continuation of the previous one.
+    if header_line.starts_with(&[SP]) || header_line.starts_with(&[HTAB]) {
+        return Err(MessageError::MalformedHeader(
+            "Obsolete line folding is not supported.",
+        ));
+    }
+
+    let colon_index = header_line
+        .iter()
+        .position(|&byte| byte == COLON)
+        .ok_or(MessageError::MalformedHeader("Missing colon."))?;
+    let name = &header_line[..colon_index];
+    // No whitespace is allowed between the field name and the colon.
+    if name.is_empty() || !name.iter().all(|&byte| is_token_char(byte)) {
+        return Err(MessageError::MalformedHeader("Invalid field name."));
+    }
+
+    let value = trim_ows(&header_line[(colon_index + 1)..]);
+    if value
+        .iter()
+        .any(|&byte| (byte < SP && byte != HTAB) || byte == DEL)
+    {
+        return Err(MessageError::MalformedHeader(
+            "Field value contains a control character.",
+        ));
+    }
+    Ok((name, value))
 }
 
 impl Headers {
     /// Expects one header line and parses it, updating the header structure or returning an
     /// error if the header is invalid.
     ///
+    /// The header line has the format `field-name ":" OWS field-value OWS`, as specified in
+    /// [RFC 7230](https://tools.ietf.org/html/rfc7230#section-3.2). Values containing
+    /// obs-text which is not valid UTF-8 are kept as bytes.
+    ///
     /// # Errors
     /// `UnsupportedHeader` is returned when the parsed header line is not of interest
     /// to us or when it is unrecognizable.
-    /// `InvalidHeader` is returned when the parsed header is formatted incorrectly or suggests
-    /// that the client is using HTTP features that we do not support in this implementation,
-    /// which invalidates the request.
+    /// `MalformedHeader` is returned when the header line is not syntactically valid, such as
+    /// when the field name is not a token, the value contains control characters or the line
+    /// is an obsolete line folding.
+    /// `InvalidHeader` is returned when the parsed header suggests that the client is using
+    /// HTTP features that we do not support in this implementation, which invalidates the
+    /// request, when it is a `Content-Length` which differs from one parsed before, or when
+    /// both a `Content-Length` and `Transfer-Encoding: chunked` are sent.
+    /// `InvalidRequest(PayloadTooLarge)` is returned when the `Content-Length` is too
+    /// large to be represented, as no body this large can be received.
     ///
     /// # Examples
     ///
@@ -95,34 +224,100 @@ impl Headers {
     ///
     /// let mut request_header = Headers::default();
     /// assert!(request_header.parse_header_line(b"Content-Length: 24").is_ok());
+    /// assert!(request_header.parse_header_line(b"Content-Length:24").is_ok());
     /// assert!(request_header.parse_header_line(b"Content-Length: 24: 2").is_err());
+    /// assert!(request_header.parse_header_line(b"Content-Length: 25").is_err());
+    /// assert!(Headers::default()
+    ///     .parse_header_line(b"Content-Length: 4294967296")
+    ///     .is_err());
+    /// assert!(request_header.parse_header_line(b"Transfer-Encoding: chunked").is_err());
+    ///
+    /// let mut chunked_header = Headers::default();
+    /// assert!(chunked_header.parse_header_line(b"Transfer-Encoding: chunked").is_ok());
+    /// assert!(chunked_header.chunked());
+    /// assert_eq!(chunked_header.header_line("Transfer-Encoding"), Some(&"chunked".to_string()));
+    /// assert!(chunked_header.parse_header_line(b"Transfer-Encoding: gzip").is_err());
+    ///
+    /// assert!(request_header.parse_header_line(b"Referer: http://a: b").is_ok());
+    /// assert_eq!(request_header.header_line("Referer"), Some(&"http://a: b".to_string()));
+    /// assert!(request_header.parse_header_line(b"Foo:  bar\t").is_ok());
+    /// assert_eq!(request_header.header_line("Foo"), Some(&"bar".to_string()));
+    /// assert!(request_header.parse_header_line(b"X-Latin: caf\xe9").is_ok());
+    /// assert_eq!(request_header.header_line_bytes("X-Latin"), Some(&b"caf\xe9"[..]));
+    ///
+    /// assert!(request_header.parse_header_line(b" folded").is_err());
+    /// assert!(request_header.parse_header_line(b"Foo : bar").is_err());
+    /// assert!(request_header.parse_header_line(b"Foo: b\x00r").is_err());
     /// ```
     pub fn parse_header_line(&mut self, header_line: &[u8]) -> Result<(), MessageError> {
-        // Headers must be ASCII, so also UTF-8 valid.
-        match std::str::from_utf8(header_line) {
-            Ok(headers_str) => {
-                let entry = headers_str.split(": ").collect::<Vec<&str>>();
-                if entry.len() != 2 {
+        let (name, value) = split_header_line(header_line)?;
+        match Header::try_from(name) {
+            Ok(Header::ContentLength) => {
+                if value.is_empty() || !value.iter().all(u8::is_ascii_digit) {
                     return Err(MessageError::InvalidHeader);
                 }
-
-                if entry[0].to_lowercase() == "content-length" {
-                    match entry[1].trim().parse::<i32>() {
-                        Ok(content_length) => {
-                            self.content_length = content_length;
-                            Ok(())
+                // The value only contains digits, so it is UTF-8 valid and it only
+                // fails to parse if it overflows.
+                let content_length = String::from_utf8_lossy(value)
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|&content_length| content_length <= i32::MAX as u64)
+                    .map(|content_length| content_length as i32)
+                    .ok_or(MessageError::InvalidRequest(RequestError::PayloadTooLarge))?;
+                // Differing lengths leave the end of the body ambiguous, which
+                // could be used to smuggle a request past another server.
+                if self.content_length_received && content_length != self.content_length {
+                    return Err(MessageError::InvalidHeader);
+                }
+                // A body can not be delimited both by its length and by chunks.
+                if self.chunked {
+                    return Err(MessageError::InvalidHeader);
+                }
+                self.content_length = content_length;
+                self.content_length_received = true;
+                Ok(())
+            }
+            Ok(Header::TransferEncoding) => {
+                // The only transfer coding we can decode is `chunked`. Any other
+                // coding would leave us unable to find where the body ends.
+                match String::from_utf8_lossy(value).to_lowercase().as_str() {
+                    "chunked" => {
+                        // A body can not be delimited both by its length and by chunks.
+                        if self.content_length_received {
+                            return Err(MessageError::InvalidHeader);
                         }
-                        Err(_) => Err(MessageError::InvalidHeader),
+                        self.chunked = true;
                     }
-                } else {
-                    self.map.insert(entry[0].to_string(), entry[1].to_string());
-                    Ok(())
+                    "identity" => {}
+                    _ => return Err(MessageError::InvalidHeader),
                 }
+                self.push_field(name, value);
+                Ok(())
+            }
+            _ => {
+                self.push_field(name, value);
+                Ok(())
             }
-            _ => Err(MessageError::InvalidHeader),
         }
     }
 
+    /// Parses a trailer field line and stores the field as it is, without changing
+    /// how the message is framed.
+    ///
+    /// # Errors
+    /// `MalformedHeader` is returned when the line is not syntactically valid.
+    pub(crate) fn parse_trailer_line(&mut self, trailer_line: &[u8]) -> Result<(), MessageError> {
+        let (name, value) = split_header_line(trailer_line)?;
+        self.push_field(name, value);
+        Ok(())
+    }
+
+    fn push_field(&mut self, name: &[u8], value: &[u8]) {
+        // Token characters are ASCII, so the name is UTF-8 valid.
+        let name = String::from_utf8_lossy(name).into_owned();
+        self.entries.push(HeaderField::from_bytes(name, value));
+    }
+
     /// Returns the content length of the body.
     pub fn content_length(&self) -> i32 {
         self.content_length
@@ -132,12 +327,106 @@ impl Headers {
         self.content_length = content_length;
     }
 
+    /// Returns `true` if the body is sent using the `chunked` transfer coding.
+    pub fn chunked(&self) -> bool {
+        self.chunked
+    }
+
+    pub fn set_chunked(&mut self, chunked: bool) {
+        self.chunked = chunked;
+    }
+
+    /// Returns the value of the first header field called `key`, ignoring case.
+    ///
+    /// If the value is not valid UTF-8, invalid sequences are replaced with `U+FFFD`.
     pub fn header_line(&self, key: &str) -> Option<&String> {
-        self.map.get(key)
+        self.entries
+            .iter()
+            .find(|field| field.name.eq_ignore_ascii_case(key))
+            .map(|field| &field.value)
+    }
+
+    /// Returns the value of the first header field called `key`, ignoring case, as
+    /// it was received.
+    pub fn header_line_bytes(&self, key: &str) -> Option<&[u8]> {
+        self.entries
+            .iter()
+            .find(|field| field.name.eq_ignore_ascii_case(key))
+            .map(HeaderField::value_bytes)
+    }
+
+    /// Returns the values of all the header fields called `key`, ignoring case,
+    /// in the order they were added.
+    pub fn header_lines(&self, key: &str) -> Vec<&str> {
+        self.entries
+            .iter()
+            .filter(|field| field.name.eq_ignore_ascii_case(key))
+            .map(|field| field.value.as_str())
+            .collect()
     }
 
+    /// Returns `true` if one of the header fields called `key` lists `token` among
+    /// its comma separated values, ignoring case.
+    pub fn contains_token(&self, key: &str, token: &str) -> bool {
+        self.header_lines(key)
+            .iter()
+            .flat_map(|value| value.split(','))
+            .any(|item| item.trim().eq_ignore_ascii_case(token))
+    }
+
+    /// Returns the header fields as a map from their names to their values.
+    ///
+    /// A field is keyed by the name it was first added with, and the values of a
+    /// field which appears several times are joined with `, `.
+    pub fn map(&self) -> HashMap<String, String> {
+        self.entries
+            .iter()
+            .enumerate()
+            .filter(|(index, field)| {
+                !self.entries[..*index]
+                    .iter()
+                    .any(|earlier| earlier.name.eq_ignore_ascii_case(&field.name))
+            })
+            .map(|(_, field)| {
+                let values = self.header_lines(&field.name).join(", ");
+                (field.name.clone(), values)
+            })
+            .collect()
+    }
+
+    /// Appends a header field, keeping any other fields with the same name.
     pub fn add_header_line(&mut self, key: String, value: String) {
-        self.map.insert(key, value);
+        self.entries.push(HeaderField::new(key, value));
+    }
+
+    /// Replaces all the header fields called `key`, ignoring case, with a single one.
+    /// The new field takes the place of the first one it replaces.
+    pub fn set_header_line(&mut self, key: String, value: String) {
+        match self
+            .entries
+            .iter()
+            .position(|field| field.name.eq_ignore_ascii_case(&key))
+        {
+            Some(index) => {
+                self.remove_header_line(&key);
+                self.entries.insert(index, HeaderField::new(key, value));
+            }
+            None => self.entries.push(HeaderField::new(key, value)),
+        }
+    }
+
+    /// Removes all the header fields called `key`, ignoring case.
+    pub fn remove_header_line(&mut self, key: &str) {
+        self.entries
+            .retain(|field| !field.name.eq_ignore_ascii_case(key));
+    }
+
+    /// Returns an iterator over the name-value pairs of the header fields, in the
+    /// order they were added.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries
+            .iter()
+            .map(|field| (field.name.as_str(), field.value.as_str()))
     }
 
     /// Parses a byte slice into a Headers structure for a HTTP request.
@@ -162,34 +451,45 @@ impl Headers {
     /// let request_headers = Headers::try_from(b"Content-Length: 55\r\n\r\n");
     /// ```
     pub fn try_from(bytes: &[u8]) -> Result<Headers, MessageError> {
-        // Headers must be ASCII, so also UTF-8 valid.
-        if let Ok(text) = std::str::from_utf8(bytes) {
-            let mut headers = Self::default();
-
-            let header_lines = text.split("\r\n");
-            for header_line in header_lines {
-                if header_line.is_empty() {
-                    break;
-                }
-                match headers.parse_header_line(header_line.as_bytes()) {
-                    Ok(_) | Err(MessageError::UnsupportedHeader) => continue,
-                    Err(e) => return Err(e),
-                };
+        let mut headers = Self::default();
+
+        for header_line in bytes.split(|&byte| byte == LF) {
+            // Each line ends in CR LF, so we have to drop the CR as well.
+            let header_line = match header_line.split_last() {
+                Some((&CR, line)) => line,
+                _ => header_line,
+            };
+            if header_line.is_empty() {
+                break;
             }
-            return Ok(headers);
+            match headers.parse_header_line(header_line) {
+                Ok(_) | Err(MessageError::UnsupportedHeader) => continue,
+                Err(e) => return Err(e),
+            };
         }
-        Err(MessageError::InvalidHeader)
+        Ok(headers)
     }
 
     pub fn write_all<T: Write>(&self, buf: &mut T) -> Result<(), WriteError> {
-        for (key, value) in &self.map {
-            buf.write_all(key.as_bytes())?;
+        // The fields which frame the body are written as told by `chunked` and
+        // `content_length` instead.
+        for field in self.entries.iter().filter(|field| {
+            !matches!(
+                Header::try_from(field.name.as_bytes()),
+                Ok(Header::TransferEncoding)
+            )
+        }) {
+            buf.write_all(field.name.as_bytes())?;
             buf.write_all(b": ")?;
-            buf.write_all(value.as_bytes())?;
+            buf.write_all(field.value_bytes())?;
             buf.write_all(b"\r\n")?;
         }
-        if self.content_length > 0 {
-            buf.write_all(b"Content-Length: ")?;
+        if self.chunked {
+            buf.write_all(Header::TransferEncoding.raw())?;
+            buf.write_all(b": chunked\r\n")?;
+        } else if self.content_length > 0 {
+            buf.write_all(Header::ContentLength.raw())?;
+            buf.write_all(b": ")?;
             buf.write_all(self.content_length.to_string().as_bytes())?;
             buf.write_all(b"\r\n")?;
         }
@@ -263,3 +563,147 @@ impl MediaType {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_headers() {
+        // Header names are matched regardless of their case.
+        let headers = Headers::try_from(
+            b"content-length: 13\r\n\
+              expect: 100-continue\r\n\
+              cookie: a=1\r\n\
+              Cookie: b=2\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(headers.content_length(), 13);
+        assert_eq!(
+            headers.header_line("Expect"),
+            Some(&"100-continue".to_string())
+        );
+        assert_eq!(headers.header_lines("COOKIE"), vec!["a=1", "b=2"]);
+        let map = headers.map();
+        assert_eq!(map.len(), 2);
+        assert_eq!(map["expect"], "100-continue");
+        assert_eq!(map["cookie"], "a=1, b=2");
+
+        // Fields are written in the order they were added, repeated ones included.
+        let mut headers = Headers::default();
+        headers.add_header_line("Set-Cookie".to_string(), "a=1".to_string());
+        headers.add_header_line("Server".to_string(), "micro_http".to_string());
+        headers.add_header_line("Set-Cookie".to_string(), "b=2".to_string());
+        let mut buf = Vec::new();
+        headers.write_all(&mut buf).unwrap();
+        assert_eq!(
+            &buf[..],
+            &b"Set-Cookie: a=1\r\nServer: micro_http\r\nSet-Cookie: b=2\r\n\r\n"[..]
+        );
+
+        // Setting a field replaces all the fields with the same name.
+        headers.set_header_line("set-cookie".to_string(), "c=3".to_string());
+        let mut buf = Vec::new();
+        headers.write_all(&mut buf).unwrap();
+        assert_eq!(
+            &buf[..],
+            &b"set-cookie: c=3\r\nServer: micro_http\r\n\r\n"[..]
+        );
+    }
+
+    #[test]
+    fn test_transfer_encoding() {
+        // The field is kept, but written as told by the framing of the message.
+        let mut headers = Headers::try_from(b"Transfer-Encoding: chunked\r\n\r\n").unwrap();
+        assert!(headers.chunked());
+        assert_eq!(headers.header_lines("transfer-encoding"), vec!["chunked"]);
+        headers.set_chunked(false);
+        let mut buf = Vec::new();
+        headers.write_all(&mut buf).unwrap();
+        assert_eq!(&buf[..], &b"\r\n"[..]);
+
+        // A body can not be delimited both by its length and by chunks.
+        assert_eq!(
+            Headers::try_from(b"Content-Length: 5\r\nTransfer-Encoding: chunked\r\n\r\n"),
+            Err(MessageError::InvalidHeader)
+        );
+        assert_eq!(
+            Headers::try_from(b"Transfer-Encoding: chunked\r\nContent-Length: 5\r\n\r\n"),
+            Err(MessageError::InvalidHeader)
+        );
+
+        // Trailer fields do not change the framing.
+        let mut trailers = Headers::default();
+        trailers.parse_trailer_line(b"Content-Length: 5").unwrap();
+        trailers
+            .parse_trailer_line(b"Transfer-Encoding: gzip")
+            .unwrap();
+        assert_eq!(trailers.content_length(), 0);
+        assert!(!trailers.chunked());
+        assert_eq!(
+            trailers.header_line("Content-Length"),
+            Some(&"5".to_string())
+        );
+        assert_eq!(
+            trailers.parse_trailer_line(b"Foo : bar"),
+            Err(MessageError::MalformedHeader("Invalid field name."))
+        );
+    }
+
+    #[test]
+    fn test_header_syntax() {
+        // The whitespace around the value is optional and not part of it.
+        let headers = Headers::try_from(
+            b"Content-Length:13\r\n\
+              Referer: http://a: b\r\n\
+              Foo:  bar \r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(headers.content_length(), 13);
+        assert_eq!(
+            headers.header_line("Referer"),
+            Some(&"http://a: b".to_string())
+        );
+        assert_eq!(headers.header_line("Foo"), Some(&"bar".to_string()));
+
+        assert_eq!(
+            Headers::try_from(b"Foo: bar\r\n\tbaz\r\n\r\n"),
+            Err(MessageError::MalformedHeader(
+                "Obsolete line folding is not supported."
+            ))
+        );
+        let mut headers = Headers::default();
+        assert_eq!(
+            headers.parse_header_line(b"Foo bar"),
+            Err(MessageError::MalformedHeader("Missing colon."))
+        );
+        assert_eq!(
+            headers.parse_header_line(b"Foo : bar"),
+            Err(MessageError::MalformedHeader("Invalid field name."))
+        );
+        assert_eq!(
+            headers.parse_header_line(b"Foo: b\x00r"),
+            Err(MessageError::MalformedHeader(
+                "Field value contains a control character."
+            ))
+        );
+    }
+
+    #[test]
+    fn test_content_length() {
+        let mut headers = Headers::default();
+        assert!(headers.parse_header_line(b"Content-Length: 24").is_ok());
+        // The same length can be sent more than once, but not a different one.
+        assert!(headers.parse_header_line(b"Content-Length: 24").is_ok());
+        assert_eq!(
+            headers.parse_header_line(b"Content-Length: 25"),
+            Err(MessageError::InvalidHeader)
+        );
+        assert_eq!(headers.content_length(), 24);
+
+        assert_eq!(
+            Headers::default().parse_header_line(b"Content-Length: 99999999999999999999"),
+            Err(MessageError::InvalidRequest(RequestError::PayloadTooLarge))
+        );
+    }
+}
@@ -2,16 +2,23 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use std::fmt::{Display, Error, Formatter};
+use std::fs::File;
+use std::io::{self, Read};
 
 pub mod epoll;
+pub mod eventfd;
 pub mod headers;
 pub mod message;
 pub mod net;
+pub mod timerfd;
 
 pub mod ascii {
     pub const CR: u8 = b'\r';
     pub const COLON: u8 = b':';
+    pub const DEL: u8 = 0x7f;
+    pub const HTAB: u8 = b'\t';
     pub const LF: u8 = b'\n';
+    pub const SEMICOLON: u8 = b';';
     pub const SP: u8 = b' ';
     pub const CRLF_LEN: usize = 2;
 }
@@ -29,6 +36,10 @@ pub enum MessageError {
     UnsupportedHeader,
     /// Header specified is invalid.
     InvalidHeader,
+    /// Header line is not syntactically valid.
+    MalformedHeader(&'static str),
+    /// A chunk of a body sent with `Transfer-Encoding: chunked` is malformed.
+    InvalidChunk(&'static str),
     /// IO error.
     IOError,
 }
@@ -41,6 +52,8 @@ impl Display for MessageError {
             Self::InvalidHttpVersion(inner) => write!(f, "Invalid HTTP Version: {}", inner),
             Self::UnsupportedHeader => write!(f, "Unsupported header."),
             Self::InvalidHeader => write!(f, "Invalid header."),
+            Self::MalformedHeader(inner) => write!(f, "Malformed header: {}", inner),
+            Self::InvalidChunk(inner) => write!(f, "Invalid chunk: {}", inner),
             Self::IOError => write!(f, "IO error."),
         }
     }
@@ -55,6 +68,12 @@ pub enum RequestError {
     InvalidUri(&'static str),
     /// The Request is invalid and cannot be served.
     InvalidRequest,
+    /// The request line is longer than the configured limit.
+    RequestLineTooLong,
+    /// The header section exceeds one of the configured limits.
+    HeaderFieldsTooLarge(&'static str),
+    /// The body is larger than the configured limit.
+    PayloadTooLarge,
 }
 
 impl Display for RequestError {
@@ -63,6 +82,11 @@ impl Display for RequestError {
             Self::InvalidHttpMethod(inner) => write!(f, "Invalid HTTP Method: {}", inner),
             Self::InvalidUri(inner) => write!(f, "Invalid URI: {}", inner),
             Self::InvalidRequest => write!(f, "Invalid request."),
+            Self::RequestLineTooLong => write!(f, "Request line is too long."),
+            Self::HeaderFieldsTooLarge(inner) => {
+                write!(f, "Header fields are too large: {}", inner)
+            }
+            Self::PayloadTooLarge => write!(f, "Payload is too large."),
         }
     }
 }
@@ -133,6 +157,14 @@ pub enum ServerError {
     ConnectionError(ConnectionError),
     /// Server maximum capacity has been reached.
     ServerFull,
+    /// The connection on which a request was received no longer exists.
+    ConnectionGone,
+    /// The client is not allowed to connect to the server.
+    PeerNotAllowed,
+    /// The server to which a response was handed no longer exists.
+    ServerGone,
+    /// The request to which a response was given was already answered.
+    AlreadyAnswered,
 }
 
 impl Display for ServerError {
@@ -141,6 +173,10 @@ impl Display for ServerError {
             Self::IOError(inner) => write!(f, "IO error: {}", inner),
             Self::ConnectionError(inner) => write!(f, "Connection error: {}", inner),
             Self::ServerFull => write!(f, "Server is full."),
+            Self::ConnectionGone => write!(f, "Connection is gone."),
+            Self::PeerNotAllowed => write!(f, "Peer is not allowed."),
+            Self::ServerGone => write!(f, "Server is gone."),
+            Self::AlreadyAnswered => write!(f, "Request was already answered."),
         }
     }
 }
@@ -184,42 +220,114 @@ impl Body {
     }
 }
 
+/// Returns `true` if `byte` is allowed in a token, as defined in
+/// [RFC 7230](https://tools.ietf.org/html/rfc7230#section-3.2.6).
+pub fn is_token_char(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte)
+}
+
+/// Turns the return value of a libc call into an `io::Result`, which holds the
+/// error set in `errno` if the call failed.
+pub(crate) fn cvt(result: libc::c_int) -> io::Result<libc::c_int> {
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
+
+/// Reads the counter of a non-blocking `eventfd` or `timerfd`, so that it is no
+/// longer readable. A counter which is already zero is left as it is.
+pub(crate) fn clear_counter(mut file: &File) -> io::Result<()> {
+    let mut counter = [0u8; 8];
+    match file.read(&mut counter) {
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(()),
+        result => result.map(|_| ()),
+    }
+}
+
 /// Supported HTTP Methods.
-#[derive(Clone, Copy, Debug, PartialEq)]
+///
+/// # Examples
+/// ```
+/// extern crate micro_http;
+/// use micro_http::Method;
+///
+/// assert_eq!(Method::try_from(b"DELETE").unwrap(), Method::Delete);
+/// assert_eq!(
+///     Method::try_from(b"PROPFIND").unwrap(),
+///     Method::Extension("PROPFIND".to_string())
+/// );
+/// assert!(Method::try_from(b"GET /").is_err());
+/// ```
+#[derive(Clone, Debug, PartialEq)]
 pub enum Method {
     /// GET Method.
     Get,
+    /// HEAD Method.
+    Head,
+    /// POST Method.
+    Post,
     /// PUT Method.
     Put,
+    /// DELETE Method.
+    Delete,
+    /// CONNECT Method.
+    Connect,
+    /// OPTIONS Method.
+    Options,
+    /// TRACE Method.
+    Trace,
     /// PATCH Method.
     Patch,
+    /// Any other method, which is a valid token but has no dedicated variant.
+    ///
+    /// Methods which have a dedicated variant are never represented as `Extension`.
+    Extension(String),
 }
 
 impl Method {
     /// Returns a `Method` object if the parsing of `bytes` is successful.
     ///
     /// The method is case sensitive. A call to try_from with the input b"get" will return
-    /// an error, but when using the input b"GET", it returns Method::Get.
+    /// `Method::Extension("get")`, but when using the input b"GET", it returns Method::Get.
     ///
     /// # Errors
-    /// `InvalidHttpMethod` is returned if the specified HTTP method is unsupported.
+    /// `InvalidHttpMethod` is returned if the specified HTTP method is not a valid token.
     pub fn try_from(bytes: &[u8]) -> Result<Self, MessageError> {
         match bytes {
             b"GET" => Ok(Self::Get),
+            b"HEAD" => Ok(Self::Head),
+            b"POST" => Ok(Self::Post),
             b"PUT" => Ok(Self::Put),
+            b"DELETE" => Ok(Self::Delete),
+            b"CONNECT" => Ok(Self::Connect),
+            b"OPTIONS" => Ok(Self::Options),
+            b"TRACE" => Ok(Self::Trace),
             b"PATCH" => Ok(Self::Patch),
+            _ if !bytes.is_empty() && bytes.iter().all(|&byte| is_token_char(byte)) => {
+                // Token characters are ASCII, so also UTF-8 valid.
+                Ok(Self::Extension(String::from_utf8_lossy(bytes).into_owned()))
+            }
             _ => Err(MessageError::InvalidRequest(
-                RequestError::InvalidHttpMethod("Unsupported HTTP method."),
+                RequestError::InvalidHttpMethod("Invalid HTTP method."),
             )),
         }
     }
 
     /// Returns an `u8 slice` corresponding to the Method.
-    pub fn raw(self) -> &'static [u8] {
+    pub fn raw(&self) -> &[u8] {
         match self {
             Self::Get => b"GET",
+            Self::Head => b"HEAD",
+            Self::Post => b"POST",
             Self::Put => b"PUT",
+            Self::Delete => b"DELETE",
+            Self::Connect => b"CONNECT",
+            Self::Options => b"OPTIONS",
+            Self::Trace => b"TRACE",
             Self::Patch => b"PATCH",
+            Self::Extension(method) => method.as_bytes(),
         }
     }
 }
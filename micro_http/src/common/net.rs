@@ -1,9 +1,139 @@
 use std::io::{Read, Write};
-use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::mem;
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::os::unix::net::{UnixListener, UnixStream};
 use std::path::Path;
 
+use common::cvt;
+
+/// Credentials of the process on the other end of a Unix domain socket, as
+/// reported by `SO_PEERCRED` when the connection was established.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PeerCredentials {
+    /// Process ID of the peer.
+    pub pid: libc::pid_t,
+    /// Effective user ID of the peer.
+    pub uid: libc::uid_t,
+    /// Effective group ID of the peer.
+    pub gid: libc::gid_t,
+}
+
+impl PeerCredentials {
+    /// Returns the credentials of the peer of the Unix domain socket `stream`.
+    fn from_stream(stream: &UnixStream) -> std::result::Result<Self, std::io::Error> {
+        // Safe because `ucred` is a plain C structure, for which all zeroes is valid.
+        let mut ucred: libc::ucred = unsafe { mem::zeroed() };
+        let mut len = mem::size_of::<libc::ucred>() as libc::socklen_t;
+        // Safe because we give a valid socket file descriptor and a buffer which is
+        // as large as the length we pass, and we check the return value.
+        cvt(unsafe {
+            libc::getsockopt(
+                stream.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_PEERCRED,
+                &mut ucred as *mut libc::ucred as *mut libc::c_void,
+                &mut len,
+            )
+        })?;
+        Ok(Self {
+            pid: ucred.pid,
+            uid: ucred.uid,
+            gid: ucred.gid,
+        })
+    }
+}
+
+/// Information about the peer of a connection.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PeerInfo {
+    /// The address of the peer of a TCP connection.
+    Tcp(SocketAddr),
+    /// The credentials of the peer of a Unix domain socket connection.
+    Uds(PeerCredentials),
+}
+
+impl PeerInfo {
+    /// Returns the address of the peer of a TCP connection.
+    pub fn addr(&self) -> Option<SocketAddr> {
+        match self {
+            Self::Tcp(addr) => Some(*addr),
+            Self::Uds(_) => None,
+        }
+    }
+
+    /// Returns the credentials of the peer of a Unix domain socket connection.
+    pub fn credentials(&self) -> Option<PeerCredentials> {
+        match self {
+            Self::Tcp(_) => None,
+            Self::Uds(credentials) => Some(*credentials),
+        }
+    }
+}
+
+/// A list of the users and groups whose processes are allowed access, which is
+/// checked against the credentials of the peer of a Unix domain socket.
+///
+/// A peer is allowed if either its user or its group is in the list. Peers
+/// without credentials, such as TCP clients, are never allowed.
+///
+/// # Example
+///
+/// ```
+/// extern crate micro_http;
+/// use micro_http::{PeerAllowList, PeerCredentials, PeerInfo};
+///
+/// let mut allow_list = PeerAllowList::new();
+/// allow_list.allow_uid(0).allow_gid(100);
+///
+/// let peer = PeerInfo::Uds(PeerCredentials {
+///     pid: 1,
+///     uid: 1000,
+///     gid: 100,
+/// });
+/// assert!(allow_list.allows(&peer));
+/// let peer = PeerInfo::Uds(PeerCredentials {
+///     pid: 1,
+///     uid: 1000,
+///     gid: 1000,
+/// });
+/// assert!(!allow_list.allows(&peer));
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PeerAllowList {
+    uids: Vec<libc::uid_t>,
+    gids: Vec<libc::gid_t>,
+}
+
+impl PeerAllowList {
+    /// Creates a list which allows no one.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allows the processes running as the user `uid`.
+    pub fn allow_uid(&mut self, uid: libc::uid_t) -> &mut Self {
+        self.uids.push(uid);
+        self
+    }
+
+    /// Allows the processes running as the group `gid`.
+    pub fn allow_gid(&mut self, gid: libc::gid_t) -> &mut Self {
+        self.gids.push(gid);
+        self
+    }
+
+    /// Returns `true` if `peer` is allowed by the list.
+    pub fn allows(&self, peer: &PeerInfo) -> bool {
+        match peer.credentials() {
+            Some(credentials) => {
+                self.uids.contains(&credentials.uid) || self.gids.contains(&credentials.gid)
+            }
+            None => false,
+        }
+    }
+}
+
 pub enum PollableListener {
     Tcp(TcpListener),
     Uds(UnixListener),
@@ -18,14 +148,23 @@ impl PollableListener {
         Ok(Self::Uds(UnixListener::bind(path)?))
     }
 
-    pub fn accept(&self) -> std::result::Result<PollableStream, std::io::Error> {
+    /// Accepts a connection along with the information about its peer. `None` is
+    /// returned when the credentials of the peer of a Unix domain socket can not
+    /// be read, in which case the connection is dropped, as the peer can not be
+    /// told apart from the others.
+    pub fn accept(
+        &self,
+    ) -> std::result::Result<Option<(PollableStream, PeerInfo)>, std::io::Error> {
         match self {
-            Self::Tcp(listener) => listener.accept().and_then(move |(stream, _)| {
-                Ok(PollableStream::Tcp(stream))
-            }),
-            Self::Uds(listener) => listener.accept().and_then(move |(stream, _)| {
-                Ok(PollableStream::Uds(stream))
+            Self::Tcp(listener) => listener.accept().map(move |(stream, addr)| {
+                Some((PollableStream::Tcp(stream), PeerInfo::Tcp(addr)))
             }),
+            Self::Uds(listener) => {
+                let (stream, _) = listener.accept()?;
+                Ok(PeerCredentials::from_stream(&stream)
+                    .ok()
+                    .map(|credentials| (PollableStream::Uds(stream), PeerInfo::Uds(credentials))))
+            }
         }
     }
 }
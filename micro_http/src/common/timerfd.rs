@@ -0,0 +1,62 @@
+use std::fs::File;
+use std::io;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::time::Duration;
+
+use libc::{
+    itimerspec, timerfd_create, timerfd_settime, timespec, CLOCK_MONOTONIC, TFD_CLOEXEC,
+    TFD_NONBLOCK,
+};
+
+use common::{clear_counter, cvt};
+
+/// Wrapper over a non-blocking `timerfd`, which becomes readable once it expires.
+#[derive(Debug)]
+pub struct TimerFd {
+    timer_fd: File,
+}
+
+impl TimerFd {
+    /// Create a new, disarmed timer measuring time with `CLOCK_MONOTONIC`.
+    pub fn new() -> io::Result<Self> {
+        // Safe because the call has no pointer arguments and we check the return value.
+        let timer_fd = cvt(unsafe { timerfd_create(CLOCK_MONOTONIC, TFD_NONBLOCK | TFD_CLOEXEC) })?;
+        // Safe because the descriptor was just created and nothing else owns it.
+        let timer_fd = unsafe { File::from_raw_fd(timer_fd) };
+        Ok(TimerFd { timer_fd })
+    }
+
+    /// Arms the timer to expire once, after `timeout`. A zero `timeout` disarms it.
+    pub fn arm(&self, timeout: Duration) -> io::Result<()> {
+        let spec = itimerspec {
+            it_interval: timespec {
+                tv_sec: 0,
+                tv_nsec: 0,
+            },
+            it_value: timespec {
+                tv_sec: timeout.as_secs() as libc::time_t,
+                tv_nsec: timeout.subsec_nanos() as libc::c_long,
+            },
+        };
+        // Safe because we give a valid timer file descriptor and a valid `itimerspec`
+        // structure, and we check the return value.
+        cvt(unsafe { timerfd_settime(self.timer_fd.as_raw_fd(), 0, &spec, std::ptr::null_mut()) })?;
+        Ok(())
+    }
+
+    /// Disarms the timer.
+    pub fn disarm(&self) -> io::Result<()> {
+        self.arm(Duration::from_secs(0))
+    }
+
+    /// Consumes the expirations of the timer, so that it is no longer readable.
+    pub fn clear(&self) -> io::Result<()> {
+        clear_counter(&self.timer_fd)
+    }
+}
+
+impl AsRawFd for TimerFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.timer_fd.as_raw_fd()
+    }
+}
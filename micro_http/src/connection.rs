@@ -1,25 +1,70 @@
 use std::collections::VecDeque;
-use std::io::{Read, Write};
+use std::io::{ErrorKind, Read, Write};
 
 use common::ascii::{CR, CRLF_LEN, LF};
-use common::Body;
 use common::message::Message;
-pub use common::{ConnectionError, RequestError, MessageError, ResponseError};
+use common::{Body, Version};
+pub use common::{ConnectionError, MessageError, RequestError};
 use headers::Headers;
-use request::{find, Request, RequestLine};
-use response::{Response, StatusCode};
+use request::{find, parse_chunk_size, Request, RequestLine};
+use response::{Response, StatusCode, StreamingBody};
+
+/// Default initial size of the read buffer, to which it shrinks back after a long line.
+pub(crate) const BUFFER_SIZE: usize = 1024;
+
+/// Limits enforced on the requests received over a connection.
+///
+/// A request exceeding one of them is answered with the matching error status:
+/// `414 URI Too Long`, `431 Request Header Fields Too Large` or
+/// `413 Payload Too Large`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RequestLimits {
+    /// Maximum length of the request line, without the ending CR LF.
+    pub max_request_line_len: usize,
+    /// Maximum number of header fields, trailer fields included.
+    pub max_header_count: usize,
+    /// Maximum number of bytes of the header section, trailer fields included.
+    pub max_headers_len: usize,
+    /// Maximum length of the body, without the chunked transfer coding framing.
+    pub max_body_len: usize,
+}
+
+/// A response which was completely written into the stream.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct SentResponse {
+    /// The status code of the response.
+    pub status_code: StatusCode,
+    /// The number of bytes written for the response, its head included.
+    pub len: usize,
+}
 
-const BUFFER_SIZE: usize = 1024;
+impl Default for RequestLimits {
+    fn default() -> Self {
+        Self {
+            max_request_line_len: 8 * 1024,
+            max_header_count: 100,
+            max_headers_len: 8 * 1024,
+            max_body_len: 1024 * 1024,
+        }
+    }
+}
 
 /// Describes the state machine of an HTTP connection.
 pub enum ConnectionState {
     WaitingForRequestLine,
     WaitingForHeaders,
     WaitingForBody,
+    WaitingForChunkSize,
+    WaitingForChunkData,
+    WaitingForTrailers,
     RequestReady,
 }
 
 /// A wrapper over a HTTP Connection.
+///
+/// Request bodies sent with `Transfer-Encoding: chunked` are reassembled before
+/// the request is handed over. The connection persists between requests as told
+/// by `Request::keep_alive`, unless a response asks for it to be closed.
 pub struct HttpConnection<T> {
     /// A partial request that is still being received.
     pending_request: Option<Request>,
@@ -29,8 +74,11 @@ pub struct HttpConnection<T> {
     /// The state of the connection regarding the current request that
     /// is being processed.
     state: ConnectionState,
-    /// Buffer where we store the bytes we read from the stream.
-    buffer: [u8; BUFFER_SIZE],
+    /// Buffer where we store the bytes we read from the stream. It grows when a
+    /// line does not fit in it, up to the size allowed by `limits`.
+    buffer: Vec<u8>,
+    /// The initial size of `buffer`, to which it shrinks back after a long line.
+    buffer_size: usize,
     /// The index in the buffer from where we have to start reading in
     /// the next `try_read` call.
     read_cursor: usize,
@@ -40,6 +88,9 @@ pub struct HttpConnection<T> {
     /// Represents how many bytes from the body of the request are still
     /// to be read.
     body_bytes_to_be_read: i32,
+    /// Represents how many bytes of the chunk that is currently being
+    /// received are still to be read, including the trailing CR LF.
+    chunk_bytes_to_be_read: usize,
     /// A queue of all requests that have been fully received and parsed.
     parsed_requests: VecDeque<Request>,
     /// A queue of requests that are waiting to be sent.
@@ -47,6 +98,41 @@ pub struct HttpConnection<T> {
     /// A buffer containing the bytes of a response that is currently
     /// being sent.
     response_buffer: Option<Vec<u8>>,
+    /// The streaming body of the response that is currently being sent,
+    /// which still has bytes to produce.
+    response_body_stream: Option<StreamingBody>,
+    /// Whether the last attempt to read from `response_body_stream` found the
+    /// source without data.
+    body_stream_blocked: bool,
+    /// Whether the connection has to be closed once all pending bytes are
+    /// written, because the last response was sent with `Connection: close`.
+    close_after_write: bool,
+    /// Tells, for each request handed over and not answered yet, whether the
+    /// connection persists after its response is sent.
+    keep_alive_queue: VecDeque<bool>,
+    /// The `100 Continue` response to the request being received, along with the
+    /// number of requests received before it which are not answered yet. It is
+    /// sent right after the responses to these requests.
+    interim_response: Option<(usize, Response)>,
+    /// Whether the bytes received from now on are ignored, because the connection
+    /// is closed after the responses to the requests already received.
+    read_closed: bool,
+    /// The limits enforced on incoming requests.
+    limits: RequestLimits,
+    /// Number of header and trailer fields of the request that is currently
+    /// being processed.
+    header_count: usize,
+    /// Number of bytes of the header and trailer fields of the request that is
+    /// currently being processed.
+    headers_len: usize,
+    /// The final response that is currently being sent.
+    response_in_progress: Option<SentResponse>,
+    /// The final responses which were completely sent since they were last popped.
+    sent_responses: VecDeque<SentResponse>,
+    /// Number of bytes read from the stream since they were last taken.
+    bytes_read: usize,
+    /// Number of bytes written into the stream since they were last taken.
+    bytes_written: usize,
 }
 
 impl<T: Read + Write> HttpConnection<T> {
@@ -56,16 +142,46 @@ impl<T: Read + Write> HttpConnection<T> {
             pending_request: None,
             stream,
             state: ConnectionState::WaitingForRequestLine,
-            buffer: [0; BUFFER_SIZE],
+            buffer: vec![0; BUFFER_SIZE],
+            buffer_size: BUFFER_SIZE,
             read_cursor: 0,
             body_vec: vec![],
             body_bytes_to_be_read: 0,
+            chunk_bytes_to_be_read: 0,
             parsed_requests: VecDeque::new(),
             response_queue: VecDeque::new(),
             response_buffer: None,
+            response_body_stream: None,
+            body_stream_blocked: false,
+            close_after_write: false,
+            keep_alive_queue: VecDeque::new(),
+            interim_response: None,
+            read_closed: false,
+            limits: RequestLimits::default(),
+            header_count: 0,
+            headers_len: 0,
+            response_in_progress: None,
+            sent_responses: VecDeque::new(),
+            bytes_read: 0,
+            bytes_written: 0,
         }
     }
 
+    /// Sets the limits enforced on the requests received from now on.
+    pub fn set_limits(&mut self, limits: RequestLimits) {
+        self.limits = limits;
+    }
+
+    /// Sets the initial size of the buffer in which the bytes read from the stream
+    /// are stored. Meant to be called before the first `try_read` call.
+    pub fn set_buffer_size(&mut self, buffer_size: usize) {
+        // An empty buffer could not hold the bytes that are read.
+        self.buffer_size = std::cmp::max(buffer_size, 1);
+        let buffer_len = std::cmp::max(self.buffer_size, self.read_cursor);
+        self.buffer.resize(buffer_len, 0);
+        self.buffer.shrink_to_fit();
+    }
+
     /// Tries to read new bytes from the stream and automatically update the request.
     /// Meant to be used only with non-blocking streams and an `EPOLL` structure.
     /// Should be called whenever an `EPOLLIN` event is signaled.
@@ -79,7 +195,34 @@ impl<T: Read + Write> HttpConnection<T> {
         // present in the buffer from a previous call of `try_read`. There are already
         // `read_cursor` bytes present in the buffer.
         let end_cursor = self.read_bytes()?;
+        if self.read_closed {
+            // Nothing will be answered after the last response, so we drop the bytes.
+            self.read_cursor = 0;
+            return Ok(());
+        }
+
+        let result = self.parse_bytes(end_cursor);
+        if result.is_err() {
+            // The requests parsed along with the one that failed are dropped, and
+            // the error response sent in its place closes the connection.
+            let dropped_count = self.parsed_requests.len();
+            self.parsed_requests.clear();
+            let answered_count = self.keep_alive_queue.len() - dropped_count;
+            self.keep_alive_queue.truncate(answered_count);
+            self.keep_alive_queue.push_back(false);
+            self.interim_response = None;
+            self.read_closed = true;
+            self.read_cursor = 0;
+        }
+        result
+    }
 
+    /// Parses the bytes in `buffer` up to `end_cursor`, moving fully received
+    /// requests into the `parsed_requests` queue.
+    ///
+    /// # Errors
+    /// `ParseError` is returned when a parsing operation fails.
+    fn parse_bytes(&mut self, end_cursor: usize) -> Result<(), ConnectionError> {
         let mut line_start_index = 0;
         loop {
             match self.state {
@@ -98,20 +241,44 @@ impl<T: Read + Write> HttpConnection<T> {
                         return Ok(());
                     }
                 }
+                ConnectionState::WaitingForChunkSize => {
+                    if !self.parse_chunk_size(&mut line_start_index, end_cursor)? {
+                        return Ok(());
+                    }
+                }
+                ConnectionState::WaitingForChunkData => {
+                    if !self.parse_chunk_data(&mut line_start_index, end_cursor)? {
+                        return Ok(());
+                    }
+                }
+                ConnectionState::WaitingForTrailers => {
+                    if !self.parse_trailers(&mut line_start_index, end_cursor)? {
+                        return Ok(());
+                    }
+                }
                 ConnectionState::RequestReady => {
                     // This request is ready to be passed for handling.
                     // Update the state machine to expect a new request and push this request into
                     // the `parsed_requests` queue.
                     self.state = ConnectionState::WaitingForRequestLine;
                     self.body_bytes_to_be_read = 0;
-                    self.parsed_requests
-                        .push_back(self.pending_request.take().unwrap());
+                    let request = self.pending_request.take().unwrap();
+                    let keep_alive = request.keep_alive();
+                    self.keep_alive_queue.push_back(keep_alive);
+                    self.parsed_requests.push_back(request);
+                    if !keep_alive {
+                        // The connection is closed after this request is answered,
+                        // so the requests that follow it are not processed.
+                        self.read_closed = true;
+                        self.read_cursor = 0;
+                        return Ok(());
+                    }
                 }
             };
         }
     }
 
-    /// Reads a maximum of 1024 bytes from the stream into `buffer`.
+    /// Reads bytes from the stream into the free space at the end of `buffer`.
     /// The return value represents the end index of what we have just appended.
     ///
     /// # Errors
@@ -128,6 +295,7 @@ impl<T: Read + Write> HttpConnection<T> {
         if bytes_read == 0 {
             return Err(ConnectionError::ConnectionClosed);
         }
+        self.bytes_read += bytes_read;
         Ok(bytes_read + self.read_cursor)
     }
 
@@ -135,7 +303,7 @@ impl<T: Read + Write> HttpConnection<T> {
     /// Returns `false` if there are no more bytes to be parsed in the buffer.
     ///
     /// # Errors
-    /// `ParseError` is returned if unable to parse request line or line longer than BUFFER_SIZE.
+    /// `ParseError` is returned if unable to parse request line or line longer than the limit.
     fn parse_request_line(
         &mut self,
         start: &mut usize,
@@ -143,9 +311,14 @@ impl<T: Read + Write> HttpConnection<T> {
     ) -> Result<bool, ConnectionError> {
         match find(&self.buffer[*start..end], &[CR, LF]) {
             Some(line_end_index) => {
+                if line_end_index > self.limits.max_request_line_len {
+                    return Err(Self::limit_error(RequestError::RequestLineTooLong));
+                }
                 let line = &self.buffer[*start..(*start + line_end_index)];
 
                 *start = *start + line_end_index + CRLF_LEN;
+                self.header_count = 0;
+                self.headers_len = 0;
 
                 // Form the request with a valid request line, which is the bare minimum
                 // for a valid request.
@@ -153,22 +326,28 @@ impl<T: Read + Write> HttpConnection<T> {
                     request_line: RequestLine::try_from(line)
                         .map_err(ConnectionError::ParseError)?,
                     headers: Headers::default(),
+                    trailers: Headers::default(),
                     body: None,
                 });
                 self.state = ConnectionState::WaitingForHeaders;
                 Ok(true)
             }
             None => {
-                // The request line is longer than BUFFER_SIZE bytes, so the request is invalid.
-                if end == BUFFER_SIZE && *start == 0 {
-                    return Err(ConnectionError::ParseError(MessageError::InvalidRequest(RequestError::InvalidRequest)));
-                } else {
-                    // Move the incomplete request line to the beginning of the buffer and wait
-                    // for the next `try_read` call to complete it.
-                    // This can only happen if another request was sent before this one, as the
-                    // limit for the length of a request line in this implementation is 1024 bytes.
-                    self.shift_buffer_left(*start, end);
+                // The request line is longer than the limit, so the request is invalid.
+                if end - *start > self.limits.max_request_line_len {
+                    return Err(Self::limit_error(RequestError::RequestLineTooLong));
                 }
+                if *start == 0 && end == self.buffer.len() {
+                    // The buffer is full with the incomplete request line, so we make room
+                    // for the rest of it.
+                    let max_len = self.limits.max_request_line_len + CRLF_LEN;
+                    if !self.grow_buffer(max_len) {
+                        return Err(Self::limit_error(RequestError::RequestLineTooLong));
+                    }
+                }
+                // Move the incomplete request line to the beginning of the buffer and wait
+                // for the next `try_read` call to complete it.
+                self.shift_buffer_left(*start, end);
                 Ok(false)
             }
         }
@@ -178,7 +357,7 @@ impl<T: Read + Write> HttpConnection<T> {
     /// Returns `false` if there are no more bytes to be parsed in the buffer.
     ///
     /// # Errors
-    /// `ParseError` is returned if unable to parse header or line longer than BUFFER_SIZE.
+    /// `ParseError` is returned if unable to parse header or line longer than the limit.
     fn parse_headers(
         &mut self,
         line_start_index: &mut usize,
@@ -195,21 +374,39 @@ impl<T: Read + Write> HttpConnection<T> {
                 // If our current state is `WaitingForHeaders`, it means that we already have
                 // a valid request formed from a request line, so it's safe to unwrap.
                 let request = self.pending_request.as_mut().unwrap();
-                if request.headers.content_length() == 0 {
-                    self.state = ConnectionState::RequestReady;
-                } else {
-                    if let Some(value) = request.header_line(&"Expect".to_string()) {
-                        if value == "100-continue" {
-                           // Send expect.
+                let expects_body =
+                    request.headers.chunked() || request.headers.content_length() != 0;
+                if !request.headers.chunked()
+                    && request.headers.content_length() as usize > self.limits.max_body_len
+                {
+                    return Err(Self::limit_error(RequestError::PayloadTooLarge));
+                }
+                if expects_body {
+                    if let Some(value) = request.header_line("Expect") {
+                        if value.eq_ignore_ascii_case("100-continue") {
+                            // Send expect.
                             let expect_response =
-                            Response::new(request.version(), StatusCode::Continue);
-                            self.response_queue.push_back(expect_response); 
+                                Response::new(request.version(), StatusCode::Continue);
+                            match self.keep_alive_queue.len() {
+                                0 => self.response_queue.push_back(expect_response),
+                                // The client would take it for the response to an
+                                // earlier request.
+                                unanswered => {
+                                    self.interim_response = Some((unanswered, expect_response))
+                                }
+                            }
                         }
                     }
+                }
 
+                if request.headers.chunked() {
+                    self.state = ConnectionState::WaitingForChunkSize;
+                } else if expects_body {
                     self.body_bytes_to_be_read = request.headers.content_length();
                     request.body = Some(Body::new(vec![]));
                     self.state = ConnectionState::WaitingForBody;
+                } else {
+                    self.state = ConnectionState::RequestReady;
                 }
 
                 // Update the index for the next header.
@@ -218,10 +415,12 @@ impl<T: Read + Write> HttpConnection<T> {
             }
             // We have found the end of a header line.
             Some(relative_line_end_index) => {
-                let request = self.pending_request.as_mut().unwrap();
                 // The `line_end_index` relative to the whole buffer.
                 let line_end_index = relative_line_end_index + *line_start_index;
 
+                self.count_header_line(relative_line_end_index)?;
+
+                let request = self.pending_request.as_mut().unwrap();
                 // Get the line slice and parse it.
                 let line = &self.buffer[*line_start_index..line_end_index];
                 match request.headers.parse_header_line(line) {
@@ -238,12 +437,7 @@ impl<T: Read + Write> HttpConnection<T> {
             }
             // If we have an incomplete header line.
             None => {
-                // If we have parsed BUFFER_SIZE bytes and still haven't found the header
-                // line end sequence.
-                if *line_start_index == 0 && end_cursor == BUFFER_SIZE {
-                    // Header line is longer than BUFFER_SIZE bytes, so it is invalid.
-                    return Err(ConnectionError::ParseError(MessageError::InvalidHeader));
-                }
+                self.check_partial_header_line(*line_start_index, end_cursor)?;
                 // Move the incomplete header line from the end of the buffer to
                 // the beginning, so that we can append the rest of the line and
                 // parse it in the next `try_read` call.
@@ -253,6 +447,57 @@ impl<T: Read + Write> HttpConnection<T> {
         }
     }
 
+    /// Accounts for a complete header or trailer line of `line_len` bytes.
+    ///
+    /// # Errors
+    /// `ParseError` is returned if the header section exceeds the limits.
+    fn count_header_line(&mut self, line_len: usize) -> Result<(), ConnectionError> {
+        self.header_count += 1;
+        self.headers_len += line_len + CRLF_LEN;
+        if self.header_count > self.limits.max_header_count {
+            return Err(Self::limit_error(RequestError::HeaderFieldsTooLarge(
+                "Too many header fields.",
+            )));
+        }
+        if self.headers_len > self.limits.max_headers_len {
+            return Err(Self::limit_error(RequestError::HeaderFieldsTooLarge(
+                "Header section is too long.",
+            )));
+        }
+        Ok(())
+    }
+
+    /// Checks that the incomplete header or trailer line between `start` and `end`
+    /// can still fit within the limits once it is complete, and makes room for the
+    /// rest of it if it fills the whole buffer.
+    ///
+    /// # Errors
+    /// `ParseError` is returned if the line is already too long.
+    fn check_partial_header_line(
+        &mut self,
+        start: usize,
+        end: usize,
+    ) -> Result<(), ConnectionError> {
+        let line_too_long = Self::limit_error(RequestError::HeaderFieldsTooLarge(
+            "Header line is too long.",
+        ));
+        if self.headers_len + (end - start) > self.limits.max_headers_len {
+            return Err(line_too_long);
+        }
+        if start == 0 && end == self.buffer.len() {
+            let max_len = self.limits.max_headers_len + CRLF_LEN;
+            if !self.grow_buffer(max_len) {
+                return Err(line_too_long);
+            }
+        }
+        Ok(())
+    }
+
+    /// Builds the error returned when a request exceeds one of the limits.
+    fn limit_error(error: RequestError) -> ConnectionError {
+        ConnectionError::ParseError(MessageError::InvalidRequest(error))
+    }
+
     /// Parses bytes in `buffer` to be put into the request body, if there should be one.
     /// Returns `false` if there are no more bytes to be parsed in the buffer.
     ///
@@ -272,11 +517,8 @@ impl<T: Read + Write> HttpConnection<T> {
                 .extend_from_slice(&self.buffer[*line_start_index..end_cursor]);
             self.body_bytes_to_be_read -= end_cursor as i32 - *line_start_index as i32;
 
-            // Clear the buffer and reset the starting index.
-            for i in 0..BUFFER_SIZE {
-                self.buffer[i] = 0;
-            }
-            self.read_cursor = 0;
+            // Reset the starting index, as we don't need anything from the buffer.
+            self.shift_buffer_left(end_cursor, end_cursor);
 
             return Ok(false);
         }
@@ -307,6 +549,143 @@ impl<T: Read + Write> HttpConnection<T> {
         Ok(true)
     }
 
+    /// Parses bytes in `buffer` for the size line of the next chunk of a chunked body.
+    /// Returns `false` if there are no more bytes to be parsed in the buffer.
+    ///
+    /// # Errors
+    /// `ParseError` is returned if the chunk size is invalid or the line is longer than
+    /// the initial size of the buffer.
+    fn parse_chunk_size(
+        &mut self,
+        line_start_index: &mut usize,
+        end_cursor: usize,
+    ) -> Result<bool, ConnectionError> {
+        match find(&self.buffer[*line_start_index..end_cursor], &[CR, LF]) {
+            Some(relative_line_end_index) => {
+                let line_end_index = relative_line_end_index + *line_start_index;
+                let chunk_size = parse_chunk_size(&self.buffer[*line_start_index..line_end_index])
+                    .map_err(ConnectionError::ParseError)?;
+                *line_start_index = line_end_index + CRLF_LEN;
+
+                if chunk_size > self.limits.max_body_len - self.body_vec.len() {
+                    return Err(Self::limit_error(RequestError::PayloadTooLarge));
+                }
+
+                if chunk_size == 0 {
+                    // The last chunk is followed by optional trailer fields.
+                    self.state = ConnectionState::WaitingForTrailers;
+                } else {
+                    // The chunk data is followed by a CR LF, which we read along with it.
+                    self.chunk_bytes_to_be_read =
+                        chunk_size
+                            .checked_add(CRLF_LEN)
+                            .ok_or(ConnectionError::ParseError(MessageError::InvalidChunk(
+                                "Chunk size is too large.",
+                            )))?;
+                    self.state = ConnectionState::WaitingForChunkData;
+                }
+                Ok(true)
+            }
+            None => {
+                if *line_start_index == 0 && end_cursor >= self.buffer_size {
+                    // Chunk size line is longer than the initial size of the buffer,
+                    // so it is invalid.
+                    return Err(ConnectionError::ParseError(MessageError::InvalidChunk(
+                        "Chunk size line is too long.",
+                    )));
+                }
+                self.shift_buffer_left(*line_start_index, end_cursor);
+                Ok(false)
+            }
+        }
+    }
+
+    /// Parses bytes in `buffer` to be appended to the body as part of the current chunk.
+    /// Returns `false` if there are no more bytes to be parsed in the buffer.
+    ///
+    /// # Errors
+    /// `ParseError` is returned when the chunk data is not followed by CR LF.
+    fn parse_chunk_data(
+        &mut self,
+        line_start_index: &mut usize,
+        end_cursor: usize,
+    ) -> Result<bool, ConnectionError> {
+        let available_bytes = end_cursor - *line_start_index;
+        // If what we have just read is not enough to complete the chunk, we keep
+        // everything and wait for more bytes.
+        if self.chunk_bytes_to_be_read > available_bytes {
+            self.body_vec
+                .extend_from_slice(&self.buffer[*line_start_index..end_cursor]);
+            self.chunk_bytes_to_be_read -= available_bytes;
+            self.shift_buffer_left(end_cursor, end_cursor);
+
+            return Ok(false);
+        }
+
+        let chunk_end_index = *line_start_index + self.chunk_bytes_to_be_read;
+        self.body_vec
+            .extend_from_slice(&self.buffer[*line_start_index..chunk_end_index]);
+        *line_start_index = chunk_end_index;
+        self.chunk_bytes_to_be_read = 0;
+
+        // Strip the CR LF which ends the chunk from the body.
+        if !self.body_vec.ends_with(&[CR, LF]) {
+            return Err(ConnectionError::ParseError(MessageError::InvalidChunk(
+                "Chunk data is not followed by CRLF.",
+            )));
+        }
+        self.body_vec.truncate(self.body_vec.len() - CRLF_LEN);
+
+        self.state = ConnectionState::WaitingForChunkSize;
+        Ok(true)
+    }
+
+    /// Parses bytes in `buffer` for the trailer fields which end a chunked body.
+    /// Returns `false` if there are no more bytes to be parsed in the buffer.
+    ///
+    /// # Errors
+    /// `ParseError` is returned if unable to parse a trailer or line longer than the limit.
+    fn parse_trailers(
+        &mut self,
+        line_start_index: &mut usize,
+        end_cursor: usize,
+    ) -> Result<bool, ConnectionError> {
+        match find(&self.buffer[*line_start_index..end_cursor], &[CR, LF]) {
+            // An empty line marks the end of the trailer section and of the request.
+            Some(0) => {
+                let request = self.pending_request.as_mut().unwrap();
+                if !self.body_vec.is_empty() {
+                    let body: Vec<_> = self.body_vec.drain(..).collect();
+                    request.body = Some(Body::new(body));
+                }
+
+                *line_start_index += CRLF_LEN;
+                self.state = ConnectionState::RequestReady;
+                Ok(true)
+            }
+            Some(relative_line_end_index) => {
+                let line_end_index = relative_line_end_index + *line_start_index;
+
+                self.count_header_line(relative_line_end_index)?;
+
+                let request = self.pending_request.as_mut().unwrap();
+                let line = &self.buffer[*line_start_index..line_end_index];
+                request
+                    .trailers
+                    .parse_trailer_line(line)
+                    .map_err(ConnectionError::ParseError)?;
+
+                *line_start_index = line_end_index + CRLF_LEN;
+                Ok(true)
+            }
+            None => {
+                self.check_partial_header_line(*line_start_index, end_cursor)?;
+                self.shift_buffer_left(*line_start_index, end_cursor);
+                Ok(false)
+            }
+        }
+    }
+
     /// Tries to write the first available response to the provided stream.
     /// Meant to be used only with non-blocking streams and an `EPOLL` structure.
     /// Should be called whenever an `EPOLLOUT` event is signaled. If no bytes
@@ -320,12 +699,51 @@ impl<T: Read + Write> HttpConnection<T> {
     /// `InvalidWrite` is returned when trying to write on a connection with an
     /// empty outgoing buffer.
     pub fn try_write(&mut self) -> Result<(), ConnectionError> {
+        self.body_stream_blocked = false;
         if self.response_buffer.is_none() {
-            if let Some(mut response) = self.response_queue.pop_front() {
+            if let Some(body_stream) = self.response_body_stream.as_mut() {
+                // Produce the next piece of the streaming body before moving on
+                // to the next response.
                 let mut response_buffer_vec: Vec<u8> = Vec::new();
-                response
-                    .send(&mut response_buffer_vec)
+                match body_stream.read_into(&mut response_buffer_vec) {
+                    Ok(()) => {}
+                    // The source has nothing for us yet.
+                    Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
+                        self.body_stream_blocked = true;
+                        return Ok(());
+                    }
+                    Err(e) => {
+                        // The body can not be completed, so the client would not be
+                        // able to tell where the next response starts.
+                        self.clear_write_buffer();
+                        return Err(ConnectionError::StreamError(e));
+                    }
+                }
+                if body_stream.is_finished() {
+                    self.response_body_stream = None;
+                }
+                if response_buffer_vec.is_empty() {
+                    self.complete_response();
+                    return Ok(());
+                }
+                self.response_buffer = Some(response_buffer_vec);
+            } else if let Some(mut response) = self.response_queue.pop_front() {
+                let mut response_buffer_vec: Vec<u8> = Vec::new();
+                self.response_body_stream = response
+                    .send_head(&mut response_buffer_vec)
                     .map_err(ConnectionError::StreamError)?;
+                // Interim responses, such as `100 Continue`, are not reported.
+                if response.status() != StatusCode::Continue {
+                    self.response_in_progress = Some(SentResponse {
+                        status_code: response.status(),
+                        len: 0,
+                    });
+                }
+                if response.connection_close() {
+                    // Nothing is sent after a response which closes the connection.
+                    self.close_after_write = true;
+                    self.response_queue.clear();
+                }
                 self.response_buffer = Some(response_buffer_vec);
             } else {
                 return Err(ConnectionError::InvalidWrite);
@@ -342,6 +760,10 @@ impl<T: Read + Write> HttpConnection<T> {
                     connection_closed = true;
                 }
                 Ok(bytes_written) => {
+                    self.bytes_written += bytes_written;
+                    if let Some(response) = self.response_in_progress.as_mut() {
+                        response.len += bytes_written;
+                    }
                     if bytes_written != bytes_to_be_written {
                         response_buffer_vec.drain(..bytes_written);
                     } else {
@@ -356,37 +778,149 @@ impl<T: Read + Write> HttpConnection<T> {
             return Err(ConnectionError::ConnectionClosed);
         } else if response_fully_written {
             self.response_buffer.take();
+            self.complete_response();
         }
 
         Ok(())
     }
 
-    fn clear_write_buffer(&mut self) {
+    /// Drops the bytes waiting to be written, along with the responses not sent yet.
+    pub fn clear_write_buffer(&mut self) {
         self.response_queue.clear();
         self.response_buffer.take();
+        self.response_body_stream.take();
+        self.body_stream_blocked = false;
+        self.response_in_progress.take();
+    }
+
+    /// Reports the response in progress as sent, once all of its bytes are written.
+    fn complete_response(&mut self) {
+        if self.response_buffer.is_none() && self.response_body_stream.is_none() {
+            if let Some(response) = self.response_in_progress.take() {
+                self.sent_responses.push_back(response);
+            }
+        }
+    }
+
+    /// Returns the first of the responses completely sent, in the order in which
+    /// they were sent, or `None` if there is none left.
+    pub(crate) fn pop_sent_response(&mut self) -> Option<SentResponse> {
+        self.sent_responses.pop_front()
+    }
+
+    /// Returns the number of bytes read from and written into the stream since
+    /// the last call.
+    pub(crate) fn take_transferred(&mut self) -> (usize, usize) {
+        let transferred = (self.bytes_read, self.bytes_written);
+        self.bytes_read = 0;
+        self.bytes_written = 0;
+        transferred
     }
 
     /// Send a response back to the source of a request.
-    pub fn enqueue_response(&mut self, response: Response) {
+    ///
+    /// Responses answer the requests in the order in which they were received. The
+    /// `Connection` header of the response is set according to whether the request
+    /// asked for the connection to persist.
+    pub fn enqueue_response(&mut self, mut response: Response) {
+        if self.close_after_write {
+            // The connection is closed once the pending bytes are written.
+            return;
+        }
+        match self.keep_alive_queue.pop_front() {
+            Some(false) => {
+                response.with_connection_close();
+            }
+            Some(true)
+                if response.http_version() == Version::Http10
+                    && response.header_line("Connection").is_none() =>
+            {
+                // HTTP/1.0 connections are only kept alive if both ends agree.
+                response.with_header("Connection".to_string(), "keep-alive".to_string());
+            }
+            _ => {}
+        }
         self.response_queue.push_back(response);
+        if let Some((unanswered, _)) = self.interim_response.as_mut() {
+            *unanswered -= 1;
+            if *unanswered == 0 {
+                let (_, interim_response) = self.interim_response.take().unwrap();
+                self.response_queue.push_back(interim_response);
+            }
+        }
     }
 
+    /// Moves the bytes between `line_start_index` and `end_cursor` to the beginning
+    /// of the buffer, so that the next `try_read` call appends to them.
     fn shift_buffer_left(&mut self, line_start_index: usize, end_cursor: usize) {
         // We don't want to shift something that is already at the beginning.
         if line_start_index != 0 {
-            // Move the bytes from `line_start_index` to the beginning of the buffer.
-            for cursor in 0..(end_cursor - line_start_index) {
-                self.buffer[cursor] = self.buffer[line_start_index + cursor];
-            }
-
-            // Clear the rest of the buffer.
-            for cursor in (end_cursor - line_start_index)..end_cursor {
-                self.buffer[cursor] = 0;
-            }
+            self.buffer.copy_within(line_start_index..end_cursor, 0);
         }
 
         // Update `read_cursor`.
         self.read_cursor = end_cursor - line_start_index;
+
+        // Release the memory taken by a long line once it is no longer needed.
+        if self.buffer.len() > self.buffer_size && self.read_cursor < self.buffer_size {
+            self.buffer.truncate(self.buffer_size);
+            self.buffer.shrink_to_fit();
+        }
+    }
+
+    /// Doubles the size of the buffer, without going over `max_len` bytes.
+    /// Returns `false` if the buffer cannot grow any further.
+    fn grow_buffer(&mut self, max_len: usize) -> bool {
+        let new_len = std::cmp::min(self.buffer.len() * 2, max_len);
+        if new_len <= self.buffer.len() {
+            return false;
+        }
+        self.buffer.resize(new_len, 0);
+        true
+    }
+
+    /// Returns `true` if part of a request was received, but not its whole
+    /// header section.
+    pub fn receiving_headers(&self) -> bool {
+        match self.state {
+            ConnectionState::WaitingForRequestLine => !self.read_closed && self.read_cursor != 0,
+            ConnectionState::WaitingForHeaders => true,
+            _ => false,
+        }
+    }
+
+    /// Returns `true` if the header section of a request was received, but not
+    /// its whole body.
+    pub fn receiving_body(&self) -> bool {
+        matches!(
+            self.state,
+            ConnectionState::WaitingForBody
+                | ConnectionState::WaitingForChunkSize
+                | ConnectionState::WaitingForChunkData
+                | ConnectionState::WaitingForTrailers
+        )
+    }
+
+    /// Drops the request that is currently being received, along with the bytes
+    /// received from now on, as the connection is closed once the pending
+    /// responses are sent.
+    pub fn discard_incoming(&mut self) {
+        self.pending_request = None;
+        self.interim_response = None;
+        self.body_vec.clear();
+        self.state = ConnectionState::WaitingForRequestLine;
+        self.read_closed = true;
+        self.read_cursor = 0;
+    }
+
+    /// Stops parsing requests, so that the connection is closed once the
+    /// responses to the requests parsed so far are sent. The last of them is sent
+    /// with `Connection: close`.
+    pub fn close_after_responses(&mut self) {
+        self.discard_incoming();
+        if let Some(keep_alive) = self.keep_alive_queue.back_mut() {
+            *keep_alive = false;
+        }
     }
 
     /// Returns the first parsed request in the queue or `None` if the queue
@@ -397,6 +931,21 @@ impl<T: Read + Write> HttpConnection<T> {
 
     /// Returns `true` if there are bytes waiting to be written into the stream.
     pub fn pending_write(&self) -> bool {
-        self.response_buffer.is_some() || !self.response_queue.is_empty()
+        self.response_buffer.is_some()
+            || self.response_body_stream.is_some()
+            || !self.response_queue.is_empty()
+    }
+
+    /// Returns `true` if the last `try_write` call wrote nothing because the source
+    /// of the streaming body being sent had no data yet.
+    pub fn body_stream_blocked(&self) -> bool {
+        self.body_stream_blocked
+    }
+
+    /// Returns `true` if the connection has to be closed once there are no more
+    /// bytes waiting to be written, either because the last response was sent with
+    /// `Connection: close` or because the end of its body is marked by closing it.
+    pub fn close_after_write(&self) -> bool {
+        self.close_after_write
     }
 }
\ No newline at end of file
@@ -7,12 +7,12 @@
 //! HTTP/1.1 has a mandatory header **Host**, but as this crate is only used
 //! for parsing API requests, this header (if present) is ignored.
 //!
-//! This HTTP implementation is stateless thus it does not support chunking or
-//! compression.
+//! This HTTP implementation is stateless thus it does not support compression.
 //!
 //! ## Supported Headers
 //! The **micro_http** crate has support for parsing the following **Request**
 //! headers:
+//! - Connection
 //! - Content-Length
 //! - Expect
 //! - Transfer-Encoding
@@ -29,19 +29,18 @@
 //! ## Supported Methods
 //! The supported HTTP Methods are:
 //! - GET
+//! - HEAD
+//! - POST
 //! - PUT
+//! - DELETE
+//! - CONNECT
+//! - OPTIONS
+//! - TRACE
 //! - PATCH
 //!
 //! ## Supported Status Codes
-//! The supported status codes are:
-//!
-//! - Continue - 100
-//! - OK - 200
-//! - No Content - 204
-//! - Bad Request - 400
-//! - Not Found - 404
-//! - Internal Server Error - 500
-//! - Not Implemented - 501
+//! All the status codes defined in RFC 7231 and RFC 6585 are supported, and any
+//! other 3 digit status code can be represented using `StatusCode::Other`.
 //!
 //! ## Example for parsing an HTTP Request from a slice
 //! ```
@@ -111,21 +110,34 @@
 
 extern crate libc;
 
+mod access_log;
 mod client;
 mod common;
 mod connection;
+mod metrics;
+mod middleware;
 mod request;
 mod response;
+mod router;
 mod server;
+mod worker_pool;
 use common::ascii;
 use common::headers;
 
+pub use access_log::{AccessLog, AccessLogFormat};
 pub use client::Client;
-pub use connection::HttpConnection;
+pub use connection::{HttpConnection, RequestLimits};
+pub use metrics::ServerMetrics;
+pub use middleware::{Middleware, MiddlewareChain};
 pub use request::{Request, RequestError};
 pub use response::{Response, ResponseError, StatusCode};
-pub use server::{HttpServer, ServerError};
+pub use router::{PathParams, Router};
+pub use server::{
+    HttpServer, HttpServerConfig, ListenerId, OverloadPolicy, RequestId, ResponseSender,
+    ServerError, ServerRequest, ServerResponse,
+};
 
 pub use common::headers::{Headers, MediaType};
 pub use common::message::Message;
+pub use common::net::{PeerAllowList, PeerCredentials, PeerInfo};
 pub use common::{Body, MessageError, Method, Version};
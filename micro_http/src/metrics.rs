@@ -0,0 +1,266 @@
+use std::collections::BTreeMap;
+
+use access_log::AccessLogEntry;
+use common::{MessageError, Method};
+
+/// A snapshot of the counters and gauges kept by an `HttpServer`.
+///
+/// The counters start from `0` when the server is created.
+///
+/// # Example
+///
+/// ```
+/// extern crate micro_http;
+/// use micro_http::HttpServer;
+///
+/// let path_to_socket = "/tmp/example_metrics.sock";
+/// std::fs::remove_file(path_to_socket).unwrap_or_default();
+///
+/// let mut server = HttpServer::new_uds(path_to_socket).unwrap();
+/// server.start_server().unwrap();
+///
+/// let metrics = server.metrics();
+/// assert_eq!(metrics.accepted_connections, 0);
+/// assert!(metrics
+///     .to_prometheus()
+///     .contains("\nmicro_http_open_connections 0\n"));
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ServerMetrics {
+    /// Number of connections accepted.
+    pub accepted_connections: u64,
+    /// Number of connections turned away because the server was full.
+    pub rejected_connections: u64,
+    /// Number of connections turned away because the client was not allowed to
+    /// connect.
+    pub forbidden_connections: u64,
+    /// Number of idle connections closed to make room for new ones.
+    pub evicted_connections: u64,
+    /// Number of requests which failed to parse, by `MessageError` variant.
+    pub parse_errors: BTreeMap<&'static str, u64>,
+    /// Number of requests answered, by method and status code of the response.
+    /// The responses sent by the server on its own are not counted, and the
+    /// extension methods are all counted as `OTHER`.
+    pub requests: BTreeMap<(String, u16), u64>,
+    /// Number of bytes read from the connections.
+    pub bytes_read: u64,
+    /// Number of bytes written into the connections.
+    pub bytes_written: u64,
+    /// Number of connections currently open.
+    pub open_connections: u64,
+}
+
+impl ServerMetrics {
+    /// Counts a request which failed to parse with `error`.
+    pub(crate) fn record_parse_error(&mut self, error: &MessageError) {
+        *self.parse_errors.entry(error_name(error)).or_insert(0) += 1;
+    }
+
+    /// Counts the response described by `entry`.
+    pub(crate) fn record_response(&mut self, entry: &AccessLogEntry) {
+        if let Some(request) = entry.request.as_ref() {
+            let method = match request.method {
+                // Clients can send any token as a method, so the extension methods
+                // share one key to keep the number of label values bounded.
+                Method::Extension(_) => "OTHER".to_string(),
+                ref method => String::from_utf8_lossy(method.raw()).into_owned(),
+            };
+            *self
+                .requests
+                .entry((method, entry.status_code.code()))
+                .or_insert(0) += 1;
+        }
+    }
+
+    /// Encodes the metrics in the Prometheus text exposition format, so that they
+    /// can be served as they are, with the `text/plain; version=0.0.4` media type.
+    pub fn to_prometheus(&self) -> String {
+        let mut text = String::new();
+        push_metric(
+            &mut text,
+            "micro_http_connections_accepted_total",
+            "counter",
+            "Connections accepted.",
+            &[(String::new(), self.accepted_connections)],
+        );
+        push_metric(
+            &mut text,
+            "micro_http_connections_rejected_total",
+            "counter",
+            "Connections turned away because the server was full.",
+            &[(String::new(), self.rejected_connections)],
+        );
+        push_metric(
+            &mut text,
+            "micro_http_connections_forbidden_total",
+            "counter",
+            "Connections turned away because the client was not allowed to connect.",
+            &[(String::new(), self.forbidden_connections)],
+        );
+        push_metric(
+            &mut text,
+            "micro_http_connections_evicted_total",
+            "counter",
+            "Idle connections closed to make room for new ones.",
+            &[(String::new(), self.evicted_connections)],
+        );
+        let parse_errors: Vec<(String, u64)> = self
+            .parse_errors
+            .iter()
+            .map(|(error, count)| (format!("{{error=\"{}\"}}", error), *count))
+            .collect();
+        push_metric(
+            &mut text,
+            "micro_http_parse_errors_total",
+            "counter",
+            "Requests which failed to parse, by error.",
+            &parse_errors,
+        );
+        // Methods are tokens, which can not contain characters that need escaping.
+        let requests: Vec<(String, u64)> = self
+            .requests
+            .iter()
+            .map(|((method, status), count)| {
+                (
+                    format!("{{method=\"{}\",status=\"{}\"}}", method, status),
+                    *count,
+                )
+            })
+            .collect();
+        push_metric(
+            &mut text,
+            "micro_http_requests_total",
+            "counter",
+            "Requests answered, by method and status code.",
+            &requests,
+        );
+        push_metric(
+            &mut text,
+            "micro_http_read_bytes_total",
+            "counter",
+            "Bytes read from the connections.",
+            &[(String::new(), self.bytes_read)],
+        );
+        push_metric(
+            &mut text,
+            "micro_http_written_bytes_total",
+            "counter",
+            "Bytes written into the connections.",
+            &[(String::new(), self.bytes_written)],
+        );
+        push_metric(
+            &mut text,
+            "micro_http_open_connections",
+            "gauge",
+            "Connections currently open.",
+            &[(String::new(), self.open_connections)],
+        );
+        text
+    }
+}
+
+/// Appends a metric to `text`, with a sample for each of the label sets in
+/// `samples`.
+fn push_metric(
+    text: &mut String,
+    name: &str,
+    metric_type: &str,
+    help: &str,
+    samples: &[(String, u64)],
+) {
+    text.push_str(&format!("# HELP {} {}\n", name, help));
+    text.push_str(&format!("# TYPE {} {}\n", name, metric_type));
+    for (labels, value) in samples.iter() {
+        text.push_str(&format!("{}{} {}\n", name, labels, value));
+    }
+}
+
+/// Returns the name of the variant of `error`.
+fn error_name(error: &MessageError) -> &'static str {
+    match error {
+        MessageError::InvalidRequest(_) => "InvalidRequest",
+        MessageError::InvalidResponse(_) => "InvalidResponse",
+        MessageError::InvalidHttpVersion(_) => "InvalidHttpVersion",
+        MessageError::UnsupportedHeader => "UnsupportedHeader",
+        MessageError::InvalidHeader => "InvalidHeader",
+        MessageError::MalformedHeader(_) => "MalformedHeader",
+        MessageError::InvalidChunk(_) => "InvalidChunk",
+        MessageError::IOError => "IOError",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::RequestError;
+
+    #[test]
+    fn test_to_prometheus() {
+        let mut metrics = ServerMetrics {
+            accepted_connections: 3,
+            rejected_connections: 1,
+            bytes_read: 120,
+            bytes_written: 240,
+            open_connections: 2,
+            ..Default::default()
+        };
+        metrics.record_parse_error(&MessageError::InvalidHeader);
+        metrics.record_parse_error(&MessageError::InvalidRequest(RequestError::InvalidUri(
+            "Empty URI not allowed.",
+        )));
+        metrics.record_parse_error(&MessageError::InvalidHeader);
+        metrics.requests.insert(("GET".to_string(), 200), 5);
+        metrics.requests.insert(("OTHER".to_string(), 404), 1);
+
+        assert_eq!(
+            metrics.to_prometheus(),
+            "# HELP micro_http_connections_accepted_total Connections accepted.\n\
+             # TYPE micro_http_connections_accepted_total counter\n\
+             micro_http_connections_accepted_total 3\n\
+             # HELP micro_http_connections_rejected_total Connections turned away because the \
+             server was full.\n\
+             # TYPE micro_http_connections_rejected_total counter\n\
+             micro_http_connections_rejected_total 1\n\
+             # HELP micro_http_connections_forbidden_total Connections turned away because the \
+             client was not allowed to connect.\n\
+             # TYPE micro_http_connections_forbidden_total counter\n\
+             micro_http_connections_forbidden_total 0\n\
+             # HELP micro_http_connections_evicted_total Idle connections closed to make room \
+             for new ones.\n\
+             # TYPE micro_http_connections_evicted_total counter\n\
+             micro_http_connections_evicted_total 0\n\
+             # HELP micro_http_parse_errors_total Requests which failed to parse, by error.\n\
+             # TYPE micro_http_parse_errors_total counter\n\
+             micro_http_parse_errors_total{error=\"InvalidHeader\"} 2\n\
+             micro_http_parse_errors_total{error=\"InvalidRequest\"} 1\n\
+             # HELP micro_http_requests_total Requests answered, by method and status code.\n\
+             # TYPE micro_http_requests_total counter\n\
+             micro_http_requests_total{method=\"GET\",status=\"200\"} 5\n\
+             micro_http_requests_total{method=\"OTHER\",status=\"404\"} 1\n\
+             # HELP micro_http_read_bytes_total Bytes read from the connections.\n\
+             # TYPE micro_http_read_bytes_total counter\n\
+             micro_http_read_bytes_total 120\n\
+             # HELP micro_http_written_bytes_total Bytes written into the connections.\n\
+             # TYPE micro_http_written_bytes_total counter\n\
+             micro_http_written_bytes_total 240\n\
+             # HELP micro_http_open_connections Connections currently open.\n\
+             # TYPE micro_http_open_connections gauge\n\
+             micro_http_open_connections 2\n"
+        );
+    }
+
+    #[test]
+    fn test_to_prometheus_without_samples() {
+        // A metric with labels and no samples only has its description.
+        let text = ServerMetrics::default().to_prometheus();
+        assert!(text.contains(
+            "# TYPE micro_http_parse_errors_total counter\n\
+             # HELP micro_http_requests_total"
+        ));
+        assert!(text.contains(
+            "# TYPE micro_http_requests_total counter\n\
+             # HELP micro_http_read_bytes_total"
+        ));
+        assert!(!text.contains('{'));
+    }
+}
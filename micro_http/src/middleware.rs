@@ -0,0 +1,253 @@
+use request::Request;
+use response::Response;
+
+/// Code which runs around the handling of a request, such as logging,
+/// authentication or adding headers to the response.
+///
+/// Both methods do nothing by default, so a middleware only implements the ones
+/// it needs.
+pub trait Middleware: Send + Sync {
+    /// Called before the request is handled. Returning a response skips the
+    /// handler and the middlewares after this one, and the response is sent
+    /// instead.
+    fn before(&self, _request: &Request) -> Option<Response> {
+        None
+    }
+
+    /// Called on the response to the request, which may be changed.
+    fn after(&self, _request: &Request, _response: &mut Response) {}
+}
+
+/// A stack of middlewares which runs around a handler.
+///
+/// The `before` methods are called in the order in which the middlewares were
+/// added and the `after` methods in the reverse order, so the first middleware
+/// sees the request first and the response last. When a middleware returns a
+/// response from `before`, only the middlewares which ran before it and itself
+/// see the response.
+///
+/// The handler can be any function of a request, including `Router::handle`, and
+/// the chain plugs into `ServerRequest::process`, as in
+/// `server_request.process(|request| chain.handle(request, |request| router.handle(request)))`.
+///
+/// # Example
+///
+/// ```
+/// extern crate micro_http;
+/// use micro_http::{
+///     Message, Middleware, MiddlewareChain, Request, Response, StatusCode, Version,
+/// };
+///
+/// struct Auth;
+///
+/// impl Middleware for Auth {
+///     fn before(&self, request: &Request) -> Option<Response> {
+///         match request.header_line("Authorization") {
+///             Some(_) => None,
+///             None => Some(Response::new(Version::Http11, StatusCode::Unauthorized)),
+///         }
+///     }
+/// }
+///
+/// struct ServerName;
+///
+/// impl Middleware for ServerName {
+///     fn after(&self, _request: &Request, response: &mut Response) {
+///         response.with_header("Server".to_string(), "example".to_string());
+///     }
+/// }
+///
+/// let mut chain = MiddlewareChain::new();
+/// chain.with(ServerName).with(Auth);
+/// let handler = |_request: &Request| Response::new(Version::Http11, StatusCode::OK);
+///
+/// let request = Request::try_from(b"GET /machine-config HTTP/1.1\r\n\r\n").unwrap();
+/// let response = chain.handle(&request, handler);
+/// assert_eq!(response.status(), StatusCode::Unauthorized);
+/// assert_eq!(response.header_line("Server").unwrap(), "example");
+///
+/// let request = Request::try_from(
+///     b"GET /machine-config HTTP/1.1\r\nAuthorization: Bearer token\r\n\r\n",
+/// )
+/// .unwrap();
+/// let response = chain.handle(&request, handler);
+/// assert_eq!(response.status(), StatusCode::OK);
+/// assert_eq!(response.header_line("Server").unwrap(), "example");
+/// ```
+#[derive(Default)]
+pub struct MiddlewareChain {
+    middlewares: Vec<Box<dyn Middleware>>,
+}
+
+impl MiddlewareChain {
+    /// Creates a chain without any middlewares.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `middleware` after the middlewares added so far.
+    pub fn with<M: Middleware + 'static>(&mut self, middleware: M) -> &mut Self {
+        self.middlewares.push(Box::new(middleware));
+        self
+    }
+
+    /// Runs `request` through the middlewares and `handler` to obtain the response.
+    pub fn handle<F>(&self, request: &Request, handler: F) -> Response
+    where
+        F: Fn(&Request) -> Response,
+    {
+        let mut response = None;
+        let mut ran_count = 0;
+        for middleware in self.middlewares.iter() {
+            ran_count += 1;
+            response = middleware.before(request);
+            if response.is_some() {
+                break;
+            }
+        }
+
+        let mut response = response.unwrap_or_else(|| handler(request));
+        for middleware in self.middlewares[..ran_count].iter().rev() {
+            middleware.after(request, &mut response);
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use common::message::Message;
+    use common::Version;
+    use response::StatusCode;
+
+    /// Records the calls to its methods in a log shared with the other
+    /// middlewares, and answers the request itself if it has a response.
+    struct Recorder {
+        name: &'static str,
+        log: Arc<Mutex<Vec<String>>>,
+        response: Option<StatusCode>,
+    }
+
+    impl Recorder {
+        fn new(name: &'static str, log: &Arc<Mutex<Vec<String>>>) -> Self {
+            Self {
+                name,
+                log: log.clone(),
+                response: None,
+            }
+        }
+    }
+
+    impl Middleware for Recorder {
+        fn before(&self, _request: &Request) -> Option<Response> {
+            self.log
+                .lock()
+                .unwrap()
+                .push(format!("{} before", self.name));
+            self.response
+                .map(|status_code| Response::new(Version::Http11, status_code))
+        }
+
+        fn after(&self, _request: &Request, response: &mut Response) {
+            self.log.lock().unwrap().push(format!(
+                "{} after {}",
+                self.name,
+                response.status().code()
+            ));
+            response.add_header("Via".to_string(), self.name.to_string());
+        }
+    }
+
+    fn handle(chain: &MiddlewareChain, log: &Arc<Mutex<Vec<String>>>) -> Response {
+        let request = Request::try_from(b"GET /machine-config HTTP/1.1\r\n\r\n").unwrap();
+        chain.handle(&request, |_request| {
+            log.lock().unwrap().push("handler".to_string());
+            Response::new(Version::Http11, StatusCode::OK)
+        })
+    }
+
+    #[test]
+    fn test_order() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut chain = MiddlewareChain::new();
+        chain
+            .with(Recorder::new("a", &log))
+            .with(Recorder::new("b", &log))
+            .with(Recorder::new("c", &log));
+
+        let mut response = handle(&chain, &log);
+        assert_eq!(response.status(), StatusCode::OK);
+        let mut buf = Vec::new();
+        response.send(&mut buf).unwrap();
+        assert_eq!(
+            &buf[..],
+            &b"HTTP/1.1 200 OK\r\nVia: c\r\nVia: b\r\nVia: a\r\n\r\n"[..]
+        );
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec![
+                "a before",
+                "b before",
+                "c before",
+                "handler",
+                "c after 200",
+                "b after 200",
+                "a after 200",
+            ]
+        );
+
+        // Without middlewares, the handler is called on its own.
+        log.lock().unwrap().clear();
+        let response = handle(&MiddlewareChain::new(), &log);
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(*log.lock().unwrap(), vec!["handler"]);
+    }
+
+    #[test]
+    fn test_short_circuit() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut forbidding = Recorder::new("b", &log);
+        forbidding.response = Some(StatusCode::Forbidden);
+        let mut chain = MiddlewareChain::new();
+        chain
+            .with(Recorder::new("a", &log))
+            .with(forbidding)
+            .with(Recorder::new("c", &log));
+
+        // Neither the handler nor the middlewares after the one answering run.
+        let response = handle(&chain, &log);
+        assert_eq!(response.status(), StatusCode::Forbidden);
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec!["a before", "b before", "b after 403", "a after 403"]
+        );
+    }
+
+    #[test]
+    fn test_response_post_processing() {
+        struct Override;
+
+        impl Middleware for Override {
+            fn after(&self, _request: &Request, response: &mut Response) {
+                *response = Response::new(Version::Http11, StatusCode::NoContent);
+            }
+        }
+
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut chain = MiddlewareChain::new();
+        chain.with(Recorder::new("a", &log)).with(Override);
+
+        // The response changed by a middleware is the one the earlier ones see.
+        let mut response = handle(&chain, &log);
+        assert_eq!(response.status(), StatusCode::NoContent);
+        assert_eq!(response.header_line("Via").unwrap(), "a");
+        assert!(response.body().is_none());
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec!["a before", "handler", "a after 204"]
+        );
+    }
+}
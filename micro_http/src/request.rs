@@ -4,7 +4,7 @@
 use std::io::{Error as WriteError, Write};
 use std::str::from_utf8;
 
-use common::ascii::{CR, CRLF_LEN, LF, SP};
+use common::ascii::{CR, CRLF_LEN, LF, SEMICOLON, SP};
 use common::message::Message;
 use common::MessageError;
 pub use common::RequestError;
@@ -21,65 +21,328 @@ pub fn find(bytes: &[u8], sequence: &[u8]) -> Option<usize> {
         .position(|window| window == sequence)
 }
 
+/// Parses the size of a chunk from a chunk size line of a chunked body.
+///
+/// The line has the format `chunk-size [ chunk-ext ]`, where `chunk-size` is a
+/// hexadecimal number. Chunk extensions carry no meaning for us, so they are ignored.
+///
+/// # Errors
+/// `InvalidChunk` is returned if the size is missing, is not a hexadecimal number
+/// or does not fit in a `usize`.
+pub fn parse_chunk_size(line: &[u8]) -> Result<usize, MessageError> {
+    let size = match find(line, &[SEMICOLON]) {
+        Some(extension_start) => &line[..extension_start],
+        None => line,
+    };
+    let size = from_utf8(size)
+        .map_err(|_| MessageError::InvalidChunk("Chunk size is not a hexadecimal number."))?
+        .trim();
+    if size.is_empty() || !size.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return Err(MessageError::InvalidChunk(
+            "Chunk size is not a hexadecimal number.",
+        ));
+    }
+    usize::from_str_radix(size, 16)
+        .map_err(|_| MessageError::InvalidChunk("Chunk size is too large."))
+}
+
+/// Returns an `InvalidUri` error with the provided reason.
+fn invalid_uri(reason: &'static str) -> MessageError {
+    MessageError::InvalidRequest(RequestError::InvalidUri(reason))
+}
+
+/// Returns `true` if `byte` can appear in a URI without being percent-encoded, as
+/// specified in [RFC 3986](https://tools.ietf.org/html/rfc3986#section-2).
+fn is_uri_char(byte: u8) -> bool {
+    // Unreserved characters, generic delimiters and sub-delimiters.
+    byte.is_ascii_alphanumeric() || b"-._~:/?#[]@!$&'()*+,;=".contains(&byte)
+}
+
+/// Decodes the percent-encoded octets in `bytes`. If `plus_as_space` is set, `+` is
+/// decoded as a space, as is customary for query strings.
+///
+/// # Errors
+/// `InvalidUri` is returned if a `%` is not followed by two hexadecimal digits or if
+/// the decoded bytes are not valid UTF-8.
+pub(crate) fn percent_decode(bytes: &[u8], plus_as_space: bool) -> Result<String, MessageError> {
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        match bytes[index] {
+            b'%' => {
+                let hex = bytes
+                    .get((index + 1)..(index + 3))
+                    .and_then(|hex| from_utf8(hex).ok())
+                    .filter(|hex| hex.bytes().all(|byte| byte.is_ascii_hexdigit()))
+                    .ok_or_else(|| invalid_uri("Invalid percent-encoding."))?;
+                // Two hexadecimal digits always fit in a byte.
+                decoded.push(u8::from_str_radix(hex, 16).unwrap());
+                index += 3;
+            }
+            b'+' if plus_as_space => {
+                decoded.push(SP);
+                index += 1;
+            }
+            byte => {
+                decoded.push(byte);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).map_err(|_| invalid_uri("Percent-encoded octets are not UTF-8."))
+}
+
 /// Wrapper over HTTP URIs.
 ///
 /// The `Uri` can not be used directly and it is only accessible from an HTTP Request.
+/// The request target is split into its components, as specified in
+/// [RFC 7230](https://tools.ietf.org/html/rfc7230#section-5.3). The path, the query
+/// parameters and the fragment are percent-decoded.
+///
+/// # Examples
+///
+/// ```
+/// extern crate micro_http;
+/// use micro_http::Request;
+///
+/// let request =
+///     Request::try_from(b"GET http://localhost:8080/vm%201?id=3&verbose#top HTTP/1.1\r\n\r\n")
+///         .unwrap();
+/// let uri = request.uri();
+/// assert_eq!(uri.scheme(), Some("http"));
+/// assert_eq!(uri.host(), Some("localhost"));
+/// assert_eq!(uri.port(), Some(8080));
+/// assert_eq!(uri.get_abs_path(), "/vm 1");
+/// assert_eq!(uri.query_param("id"), Some("3"));
+/// assert_eq!(uri.query_param("verbose"), Some(""));
+/// assert_eq!(uri.fragment(), Some("top"));
+///
+/// assert!(Request::try_from(b"GET /vm%2 HTTP/1.1\r\n\r\n").is_err());
+/// ```
 #[derive(Clone, Debug, Default, PartialEq)]
 pub struct Uri {
+    /// The URI as it was received. Changing it does not change the components of
+    /// the URI returned by the other methods.
     pub path: String,
+    /// The scheme of an absolute URI, in lower case.
+    scheme: Option<String>,
+    /// The host from the authority component.
+    host: Option<String>,
+    /// The port from the authority component.
+    port: Option<u16>,
+    /// The path as it was received.
+    raw_path: String,
+    /// The percent-decoded path.
+    decoded_path: String,
+    /// The percent-decoded query parameters, in the order they were received.
+    query: Vec<(String, String)>,
+    /// The percent-decoded fragment.
+    fragment: Option<String>,
 }
 
 impl Uri {
+    /// Creates a `Uri` for an outgoing request. If `slice` can not be parsed, it is
+    /// still sent as it is, but none of its components are available.
     fn new(slice: &str) -> Self {
-        Self {
+        Self::try_from(slice.as_bytes()).unwrap_or_else(|_| Self {
             path: String::from(slice),
-        }
+            ..Default::default()
+        })
     }
 
     fn try_from(bytes: &[u8]) -> Result<Self, MessageError> {
         if bytes.is_empty() {
-            return Err(MessageError::InvalidRequest(RequestError::InvalidUri(
-                "Empty URI not allowed.",
-            )));
+            return Err(invalid_uri("Empty URI not allowed."));
+        }
+        if let Some(&byte) = bytes
+            .iter()
+            .find(|&&byte| !is_uri_char(byte) && byte != b'%')
+        {
+            return Err(if byte.is_ascii() {
+                invalid_uri("URI contains a character which is not allowed.")
+            } else {
+                invalid_uri("URI contains a non-ASCII character.")
+            });
         }
-        let utf8_slice = from_utf8(bytes).map_err(|_| {
-            MessageError::InvalidRequest(RequestError::InvalidUri("Cannot parse URI as UTF-8."))
-        })?;
-        Ok(Self::new(utf8_slice))
+        // All the bytes are ASCII, so also UTF-8 valid.
+        let raw = from_utf8(bytes).unwrap();
+
+        let mut uri = Self {
+            path: String::from(raw),
+            ..Default::default()
+        };
+
+        let (target, fragment) = match raw.find('#') {
+            Some(index) => (&raw[..index], Some(&raw[(index + 1)..])),
+            None => (raw, None),
+        };
+        if let Some(fragment) = fragment {
+            if fragment.contains('#') {
+                return Err(invalid_uri("URI contains more than one '#'."));
+            }
+            uri.fragment = Some(percent_decode(fragment.as_bytes(), false)?);
+        }
+
+        let (target, query) = match target.find('?') {
+            Some(index) => (&target[..index], Some(&target[(index + 1)..])),
+            None => (target, None),
+        };
+        if let Some(query) = query {
+            uri.query = Self::parse_query(query)?;
+        }
+
+        let path = if target == "*" || target.starts_with('/') {
+            // Asterisk form or origin form.
+            target
+        } else if let Some(scheme_end) = target.find("://") {
+            // Absolute form.
+            let scheme = &target[..scheme_end];
+            if !Self::is_valid_scheme(scheme) {
+                return Err(invalid_uri("Invalid URI scheme."));
+            }
+            uri.scheme = Some(scheme.to_ascii_lowercase());
+
+            let authority_and_path = &target[(scheme_end + 3)..];
+            let authority_end = authority_and_path
+                .find('/')
+                .unwrap_or(authority_and_path.len());
+            uri.parse_authority(&authority_and_path[..authority_end])?;
+            &authority_and_path[authority_end..]
+        } else if query.is_none() && fragment.is_none() {
+            // Authority form, which is used by CONNECT requests.
+            uri.parse_authority(target)?;
+            if uri.port.is_none() {
+                return Err(invalid_uri("URI in authority form must contain a port."));
+            }
+            ""
+        } else {
+            return Err(invalid_uri("URI path must be absolute."));
+        };
+        uri.raw_path = String::from(path);
+        uri.decoded_path = percent_decode(path.as_bytes(), false)?;
+
+        Ok(uri)
+    }
+
+    /// Returns `true` if `scheme` is a valid URI scheme.
+    fn is_valid_scheme(scheme: &str) -> bool {
+        scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+            && scheme
+                .bytes()
+                .all(|byte| byte.is_ascii_alphanumeric() || b"+-.".contains(&byte))
     }
 
-    /// Returns the absolute path of the `Uri`.
+    /// Parses the `[ userinfo "@" ] host [ ":" port ]` authority component.
+    /// The user information is deprecated in HTTP, so it is ignored.
+    fn parse_authority(&mut self, authority: &str) -> Result<(), MessageError> {
+        let host_and_port = match authority.rfind('@') {
+            Some(index) => &authority[(index + 1)..],
+            None => authority,
+        };
+        // An IPv6 address is enclosed in brackets and contains colons.
+        let port_start = match host_and_port.rfind(':') {
+            Some(index) if !host_and_port[index..].contains(']') => Some(index),
+            _ => None,
+        };
+        let (host, port) = match port_start {
+            Some(index) => (&host_and_port[..index], &host_and_port[(index + 1)..]),
+            None => (host_and_port, ""),
+        };
+
+        if host.is_empty() {
+            return Err(invalid_uri("URI authority must contain a host."));
+        }
+        self.host = Some(percent_decode(host.as_bytes(), false)?);
+        if !port.is_empty() {
+            if !port.bytes().all(|byte| byte.is_ascii_digit()) {
+                return Err(invalid_uri("URI port is not a number."));
+            }
+            self.port = Some(
+                port.parse::<u16>()
+                    .map_err(|_| invalid_uri("URI port is out of range."))?,
+            );
+        }
+        Ok(())
+    }
+
+    /// Parses the `name=value` pairs of a query string, separated by `&`.
+    /// A parameter without a `=` has an empty value.
+    fn parse_query(query: &str) -> Result<Vec<(String, String)>, MessageError> {
+        query
+            .split('&')
+            .filter(|parameter| !parameter.is_empty())
+            .map(|parameter| {
+                let (name, value) = match parameter.find('=') {
+                    Some(index) => (&parameter[..index], &parameter[(index + 1)..]),
+                    None => (parameter, ""),
+                };
+                Ok((
+                    percent_decode(name.as_bytes(), true)?,
+                    percent_decode(value.as_bytes(), true)?,
+                ))
+            })
+            .collect()
+    }
+
+    /// Returns the URI as it was received.
+    pub fn as_str(&self) -> &str {
+        self.path.as_str()
+    }
+
+    /// Returns the scheme of an absolute URI, in lower case.
+    pub fn scheme(&self) -> Option<&str> {
+        self.scheme.as_deref()
+    }
+
+    /// Returns the host of an absolute URI or of a URI in authority form.
+    pub fn host(&self) -> Option<&str> {
+        self.host.as_deref()
+    }
+
+    /// Returns the port of an absolute URI or of a URI in authority form, if one
+    /// was specified.
+    pub fn port(&self) -> Option<u16> {
+        self.port
+    }
+
+    /// Returns the percent-decoded query parameters, in the order they were received.
+    pub fn query(&self) -> &[(String, String)] {
+        &self.query
+    }
+
+    /// Returns the value of the first query parameter called `name`.
+    pub fn query_param(&self, name: &str) -> Option<&str> {
+        self.query
+            .iter()
+            .find(|(parameter, _)| parameter == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns the percent-decoded fragment.
+    pub fn fragment(&self) -> Option<&str> {
+        self.fragment.as_deref()
+    }
+
+    /// Returns the percent-decoded absolute path of the `Uri`.
     ///
     /// URIs can be represented in absolute form or relative form. The absolute form includes
-    /// the HTTP scheme, followed by the absolute path as follows:
+    /// the scheme, followed by the authority and the absolute path as follows:
     /// "http:" "//" host [ ":" port ] [ abs_path ]
-    /// The relative URIs can be one of net_path | abs_path | rel_path.
-    /// This method only handles absolute URIs and relative URIs specified by abs_path.
-    /// The abs_path is expected to start with '/'.
+    /// The relative form only contains the abs_path, which is expected to start with '/'.
+    /// The query and the fragment are not part of the path.
     ///
     /// # Errors
-    /// Returns an empty byte array when the host or the path are empty/invalid.
+    /// Returns an empty string when the URI has no path, as is the case for absolute URIs
+    /// without one and for URIs in authority form.
     pub fn get_abs_path(&self) -> &str {
-        const HTTP_SCHEME_PREFIX: &str = "http://";
-
-        if self.path.starts_with(HTTP_SCHEME_PREFIX) {
-            let without_scheme = &self.path[HTTP_SCHEME_PREFIX.len()..];
-            if without_scheme.is_empty() {
-                return "";
-            }
-            // The host in this case includes the port and contains the bytes after http:// up to
-            // the next '/'.
-            match without_scheme.bytes().position(|byte| byte == b'/') {
-                Some(len) => &without_scheme[len..],
-                None => "",
-            }
-        } else {
-            if self.path.starts_with('/') {
-                return self.path.as_str();
-            }
+        self.decoded_path.as_str()
+    }
 
-            ""
-        }
+    /// Returns the absolute path of the `Uri` as it was received, before
+    /// percent-decoding, so that encoded slashes can be told apart from the
+    /// ones separating the segments.
+    pub(crate) fn raw_path(&self) -> &str {
+        self.raw_path.as_str()
     }
 }
 
@@ -131,7 +394,7 @@ impl RequestLine {
     pub fn write_all<T: Write>(&self, buf: &mut T) -> Result<(), WriteError> {
         buf.write_all(self.method.raw())?;
         buf.write_all(&[SP])?;
-        buf.write_all(self.uri.path.as_bytes())?;
+        buf.write_all(self.uri.as_str().as_bytes())?;
         buf.write_all(&[SP])?;
         buf.write_all(self.http_version.raw())?;
         buf.write_all(&[CR, LF])?;
@@ -140,10 +403,10 @@ impl RequestLine {
     }
 
     // Returns the minimum length of a valid request. The request must contain
-    // the method (GET), the URI (minmum 1 character), the HTTP version(HTTP/DIGIT.DIGIT) and
-    // 2 separators (SP).
+    // the method (minimum 1 character), the URI (minmum 1 character), the HTTP
+    // version(HTTP/DIGIT.DIGIT) and 2 separators (SP).
     fn min_len() -> usize {
-        Method::Get.raw().len() + 1 + Version::Http10.raw().len() + 2
+        1 + 1 + Version::Http10.raw().len() + 2
     }
 }
 
@@ -155,6 +418,9 @@ pub struct Request {
     pub request_line: RequestLine,
     /// The headers of the request.
     pub headers: Headers,
+    /// The trailer fields sent after the last chunk of a chunked request.
+    /// Empty if the request was not chunked or had no trailers.
+    pub trailers: Headers,
     /// The body of the request.
     pub body: Option<Body>,
 }
@@ -169,21 +435,23 @@ impl Message for Request {
 
         self.request_line.write_all(out)?;
         self.headers.write_all(out)?;
-        match self.body.as_mut() {
-            Some(body) => {
-                let mut slice: &[u8] = body.as_stream().as_mut_slice();
-                std::io::copy(&mut slice, out)?;
-            }
-            None => {}
+        if let Some(body) = self.body.as_mut() {
+            let mut slice: &[u8] = body.as_stream().as_mut_slice();
+            std::io::copy(&mut slice, out)?;
         }
         Ok(())
     }
 
-    fn header_line(&self, key: &String) -> Option<&String> {
+    fn header_line(&self, key: &str) -> Option<&String> {
         self.headers.header_line(key)
     }
 
     fn with_header(&mut self, key: String, value: String) -> &mut Self {
+        self.headers.set_header_line(key, value);
+        self
+    }
+
+    fn add_header(&mut self, key: String, value: String) -> &mut Self {
         self.headers.add_header_line(key, value);
         self
     }
@@ -216,8 +484,8 @@ impl Request {
     ///     * Entity Body - Optional </br>
     /// The request headers and the entity body is not parsed and None is returned because
     /// these are not used by the MMDS server.
-    /// The only supported method is GET and the HTTP protocol is expected to be HTTP/1.0
-    /// or HTTP/1.1.
+    /// Any method which is a valid token is accepted and the HTTP protocol is expected
+    /// to be HTTP/1.0 or HTTP/1.1.
     ///
     /// # Errors
     /// The function returns InvalidRequest when parsing the byte stream fails.
@@ -253,6 +521,7 @@ impl Request {
             Some(0) => Ok(Self {
                 request_line,
                 headers: Headers::default(),
+                trailers: Headers::default(),
                 body: None,
             }),
             Some(headers_end) => {
@@ -291,6 +560,7 @@ impl Request {
                 Ok(Self {
                     request_line,
                     headers,
+                    trailers: Headers::default(),
                     body,
                 })
             }
@@ -310,6 +580,7 @@ impl Request {
         Self {
             request_line,
             headers: Headers::default(),
+            trailers: Headers::default(),
             body: None,
         }
     }
@@ -323,6 +594,104 @@ impl Request {
 
     /// Returns the HTTP `Method` of the `Request`.
     pub fn method(&self) -> Method {
-        self.request_line.method
+        self.request_line.method.clone()
+    }
+
+    /// Returns the trailer fields received after the body of a chunked `Request`.
+    pub fn trailers(&self) -> &Headers {
+        &self.trailers
+    }
+
+    /// Returns `true` if the client wants the connection to stay open after the
+    /// response to this `Request` is sent.
+    ///
+    /// HTTP/1.1 connections persist unless the client sends `Connection: close`,
+    /// while HTTP/1.0 connections only persist if it sends `Connection: keep-alive`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// extern crate micro_http;
+    /// use micro_http::Request;
+    ///
+    /// let request = Request::try_from(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+    /// assert!(!request.keep_alive());
+    /// let request =
+    ///     Request::try_from(b"GET / HTTP/1.0\r\nConnection: Keep-Alive\r\n\r\n").unwrap();
+    /// assert!(request.keep_alive());
+    /// ```
+    pub fn keep_alive(&self) -> bool {
+        if self.headers.contains_token("Connection", "close") {
+            return false;
+        }
+        match self.request_line.http_version {
+            Version::Http10 => self.headers.contains_token("Connection", "keep-alive"),
+            Version::Http11 => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_methods() {
+        let methods: Vec<Method> = [
+            &b"POST /snapshot HTTP/1.1\r\n\r\n"[..],
+            &b"DELETE /drives/1 HTTP/1.1\r\n\r\n"[..],
+            &b"PROPFIND /drives HTTP/1.1\r\n\r\n"[..],
+        ]
+        .iter()
+        .map(|bytes| Request::try_from(bytes).unwrap().method())
+        .collect();
+        assert_eq!(
+            methods,
+            vec![
+                Method::Post,
+                Method::Delete,
+                Method::Extension("PROPFIND".to_string())
+            ]
+        );
+        // A method has to be a token.
+        assert!(Request::try_from(b"GE(T / HTTP/1.1\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn test_uri() {
+        let request =
+            Request::try_from(b"GET /vm?id=3&name=my%20vm&id=4 HTTP/1.1\r\n\r\n").unwrap();
+        let uri = request.uri();
+        assert_eq!(uri.get_abs_path(), "/vm");
+        assert_eq!(
+            uri.query(),
+            &[
+                ("id".to_string(), "3".to_string()),
+                ("name".to_string(), "my vm".to_string()),
+                ("id".to_string(), "4".to_string()),
+            ]
+        );
+        assert_eq!(uri.query_param("id"), Some("3"));
+        assert_eq!(uri.path, "/vm?id=3&name=my%20vm&id=4");
+
+        // The path is also kept as it was received, in which encoded slashes
+        // are told apart from the ones separating the segments.
+        let request = Request::try_from(b"GET /vms/4%2F2 HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(request.uri().get_abs_path(), "/vms/4/2");
+        assert_eq!(request.uri().raw_path(), "/vms/4%2F2");
+
+        // An invalid URI is reported with the precise reason.
+        assert_eq!(
+            Request::try_from(b"GET /vm\"1 HTTP/1.1\r\n\r\n"),
+            Err(MessageError::InvalidRequest(RequestError::InvalidUri(
+                "URI contains a character which is not allowed."
+            )))
+        );
+        assert_eq!(
+            Request::try_from(b"GET /vm%2 HTTP/1.1\r\n\r\n"),
+            Err(MessageError::InvalidRequest(RequestError::InvalidUri(
+                "Invalid percent-encoding."
+            )))
+        );
     }
 }
@@ -1,7 +1,7 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
-use std::io::{Error as WriteError, Read, Write};
+use std::io::{Error as WriteError, ErrorKind, Read, Write};
 
 use ascii::{CR, CRLF_LEN, LF, SP};
 use common::message::Message;
@@ -10,56 +10,328 @@ use common::{Body, MessageError, Version};
 use headers::Headers;
 use request::find;
 
+/// The maximum number of bytes read from the source of a streaming body at once.
+const STREAM_CHUNK_SIZE: usize = 4096;
+
 /// Wrapper over a response status code.
 ///
 /// The status code is defined as specified in the
-/// [RFC](https://tools.ietf.org/html/rfc7231#section-6).
-#[allow(dead_code)]
+/// [RFC](https://tools.ietf.org/html/rfc7231#section-6) and in
+/// [RFC 6585](https://tools.ietf.org/html/rfc6585).
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum StatusCode {
     /// 100, Continue
     Continue,
+    /// 101, Switching Protocols
+    SwitchingProtocols,
     /// 200, OK
     OK,
+    /// 201, Created
+    Created,
+    /// 202, Accepted
+    Accepted,
+    /// 203, Non-Authoritative Information
+    NonAuthoritativeInformation,
     /// 204, No Content
     NoContent,
+    /// 205, Reset Content
+    ResetContent,
+    /// 206, Partial Content
+    PartialContent,
+    /// 300, Multiple Choices
+    MultipleChoices,
+    /// 301, Moved Permanently
+    MovedPermanently,
+    /// 302, Found
+    Found,
+    /// 303, See Other
+    SeeOther,
+    /// 304, Not Modified
+    NotModified,
+    /// 305, Use Proxy
+    UseProxy,
+    /// 307, Temporary Redirect
+    TemporaryRedirect,
     /// 400, Bad Request
     BadRequest,
+    /// 401, Unauthorized
+    Unauthorized,
+    /// 402, Payment Required
+    PaymentRequired,
+    /// 403, Forbidden
+    Forbidden,
     /// 404, Not Found
     NotFound,
+    /// 405, Method Not Allowed
+    MethodNotAllowed,
+    /// 406, Not Acceptable
+    NotAcceptable,
+    /// 407, Proxy Authentication Required
+    ProxyAuthenticationRequired,
+    /// 408, Request Timeout
+    RequestTimeout,
+    /// 409, Conflict
+    Conflict,
+    /// 410, Gone
+    Gone,
+    /// 411, Length Required
+    LengthRequired,
+    /// 412, Precondition Failed
+    PreconditionFailed,
+    /// 413, Payload Too Large
+    PayloadTooLarge,
+    /// 414, URI Too Long
+    UriTooLong,
+    /// 415, Unsupported Media Type
+    UnsupportedMediaType,
+    /// 416, Range Not Satisfiable
+    RangeNotSatisfiable,
+    /// 417, Expectation Failed
+    ExpectationFailed,
+    /// 426, Upgrade Required
+    UpgradeRequired,
+    /// 428, Precondition Required
+    PreconditionRequired,
+    /// 429, Too Many Requests
+    TooManyRequests,
+    /// 431, Request Header Fields Too Large
+    RequestHeaderFieldsTooLarge,
     /// 500, Internal Server Error
     InternalServerError,
     /// 501, Not Implemented
     NotImplemented,
+    /// 502, Bad Gateway
+    BadGateway,
+    /// 503, Service Unavailable
+    ServiceUnavailable,
+    /// 504, Gateway Timeout
+    GatewayTimeout,
+    /// 505, HTTP Version Not Supported
+    HttpVersionNotSupported,
+    /// 511, Network Authentication Required
+    NetworkAuthenticationRequired,
+    /// Any other well-formed status code, which has no default reason phrase.
+    ///
+    /// Codes which have a dedicated variant are never represented as `Other`.
+    Other(u16),
+}
+
+/// Builds the table of the digits of the status codes, indexed by code.
+const fn status_code_digits() -> [[u8; 3]; 1000] {
+    let mut digits = [[0u8; 3]; 1000];
+    let mut code = 0;
+    while code < digits.len() {
+        digits[code] = [
+            b'0' + (code / 100) as u8,
+            b'0' + (code / 10 % 10) as u8,
+            b'0' + (code % 10) as u8,
+        ];
+        code += 1;
+    }
+    digits
 }
 
 impl StatusCode {
-    /// Returns the status code as bytes.
-    pub fn raw(self) -> &'static [u8; 3] {
+    /// Returns the numeric value of the status code.
+    pub fn code(self) -> u16 {
+        match self {
+            Self::Continue => 100,
+            Self::SwitchingProtocols => 101,
+            Self::OK => 200,
+            Self::Created => 201,
+            Self::Accepted => 202,
+            Self::NonAuthoritativeInformation => 203,
+            Self::NoContent => 204,
+            Self::ResetContent => 205,
+            Self::PartialContent => 206,
+            Self::MultipleChoices => 300,
+            Self::MovedPermanently => 301,
+            Self::Found => 302,
+            Self::SeeOther => 303,
+            Self::NotModified => 304,
+            Self::UseProxy => 305,
+            Self::TemporaryRedirect => 307,
+            Self::BadRequest => 400,
+            Self::Unauthorized => 401,
+            Self::PaymentRequired => 402,
+            Self::Forbidden => 403,
+            Self::NotFound => 404,
+            Self::MethodNotAllowed => 405,
+            Self::NotAcceptable => 406,
+            Self::ProxyAuthenticationRequired => 407,
+            Self::RequestTimeout => 408,
+            Self::Conflict => 409,
+            Self::Gone => 410,
+            Self::LengthRequired => 411,
+            Self::PreconditionFailed => 412,
+            Self::PayloadTooLarge => 413,
+            Self::UriTooLong => 414,
+            Self::UnsupportedMediaType => 415,
+            Self::RangeNotSatisfiable => 416,
+            Self::ExpectationFailed => 417,
+            Self::UpgradeRequired => 426,
+            Self::PreconditionRequired => 428,
+            Self::TooManyRequests => 429,
+            Self::RequestHeaderFieldsTooLarge => 431,
+            Self::InternalServerError => 500,
+            Self::NotImplemented => 501,
+            Self::BadGateway => 502,
+            Self::ServiceUnavailable => 503,
+            Self::GatewayTimeout => 504,
+            Self::HttpVersionNotSupported => 505,
+            Self::NetworkAuthenticationRequired => 511,
+            Self::Other(code) => code,
+        }
+    }
+
+    /// Returns the default reason phrase of the status code, or an empty string
+    /// if the code is not known to us.
+    pub fn reason_phrase(self) -> &'static str {
         match self {
-            Self::Continue => b"100",
-            Self::OK => b"200",
-            Self::NoContent => b"204",
-            Self::BadRequest => b"400",
-            Self::NotFound => b"404",
-            Self::InternalServerError => b"500",
-            Self::NotImplemented => b"501",
+            Self::Continue => "Continue",
+            Self::SwitchingProtocols => "Switching Protocols",
+            Self::OK => "OK",
+            Self::Created => "Created",
+            Self::Accepted => "Accepted",
+            Self::NonAuthoritativeInformation => "Non-Authoritative Information",
+            Self::NoContent => "No Content",
+            Self::ResetContent => "Reset Content",
+            Self::PartialContent => "Partial Content",
+            Self::MultipleChoices => "Multiple Choices",
+            Self::MovedPermanently => "Moved Permanently",
+            Self::Found => "Found",
+            Self::SeeOther => "See Other",
+            Self::NotModified => "Not Modified",
+            Self::UseProxy => "Use Proxy",
+            Self::TemporaryRedirect => "Temporary Redirect",
+            Self::BadRequest => "Bad Request",
+            Self::Unauthorized => "Unauthorized",
+            Self::PaymentRequired => "Payment Required",
+            Self::Forbidden => "Forbidden",
+            Self::NotFound => "Not Found",
+            Self::MethodNotAllowed => "Method Not Allowed",
+            Self::NotAcceptable => "Not Acceptable",
+            Self::ProxyAuthenticationRequired => "Proxy Authentication Required",
+            Self::RequestTimeout => "Request Timeout",
+            Self::Conflict => "Conflict",
+            Self::Gone => "Gone",
+            Self::LengthRequired => "Length Required",
+            Self::PreconditionFailed => "Precondition Failed",
+            Self::PayloadTooLarge => "Payload Too Large",
+            Self::UriTooLong => "URI Too Long",
+            Self::UnsupportedMediaType => "Unsupported Media Type",
+            Self::RangeNotSatisfiable => "Range Not Satisfiable",
+            Self::ExpectationFailed => "Expectation Failed",
+            Self::UpgradeRequired => "Upgrade Required",
+            Self::PreconditionRequired => "Precondition Required",
+            Self::TooManyRequests => "Too Many Requests",
+            Self::RequestHeaderFieldsTooLarge => "Request Header Fields Too Large",
+            Self::InternalServerError => "Internal Server Error",
+            Self::NotImplemented => "Not Implemented",
+            Self::BadGateway => "Bad Gateway",
+            Self::ServiceUnavailable => "Service Unavailable",
+            Self::GatewayTimeout => "Gateway Timeout",
+            Self::HttpVersionNotSupported => "HTTP Version Not Supported",
+            Self::NetworkAuthenticationRequired => "Network Authentication Required",
+            Self::Other(_) => "",
         }
     }
 
+    /// Returns the `StatusCode` which corresponds to `code`, falling back to
+    /// `Other` for codes without a dedicated variant.
+    ///
+    /// # Errors
+    /// `InvalidStatusCode` is returned if `code` is not a 3 digit number.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// extern crate micro_http;
+    /// use micro_http::StatusCode;
+    ///
+    /// assert_eq!(StatusCode::from_code(503).unwrap(), StatusCode::ServiceUnavailable);
+    /// assert_eq!(StatusCode::from_code(599).unwrap(), StatusCode::Other(599));
+    /// assert_eq!(StatusCode::from_code(599).unwrap().raw(), b"599");
+    /// assert!(StatusCode::from_code(1000).is_err());
+    /// ```
+    pub fn from_code(code: u16) -> Result<Self, MessageError> {
+        if !(100..=999).contains(&code) {
+            return Err(MessageError::InvalidResponse(
+                ResponseError::InvalidStatusCode("Status code must have 3 digits."),
+            ));
+        }
+        Ok(match code {
+            100 => Self::Continue,
+            101 => Self::SwitchingProtocols,
+            200 => Self::OK,
+            201 => Self::Created,
+            202 => Self::Accepted,
+            203 => Self::NonAuthoritativeInformation,
+            204 => Self::NoContent,
+            205 => Self::ResetContent,
+            206 => Self::PartialContent,
+            300 => Self::MultipleChoices,
+            301 => Self::MovedPermanently,
+            302 => Self::Found,
+            303 => Self::SeeOther,
+            304 => Self::NotModified,
+            305 => Self::UseProxy,
+            307 => Self::TemporaryRedirect,
+            400 => Self::BadRequest,
+            401 => Self::Unauthorized,
+            402 => Self::PaymentRequired,
+            403 => Self::Forbidden,
+            404 => Self::NotFound,
+            405 => Self::MethodNotAllowed,
+            406 => Self::NotAcceptable,
+            407 => Self::ProxyAuthenticationRequired,
+            408 => Self::RequestTimeout,
+            409 => Self::Conflict,
+            410 => Self::Gone,
+            411 => Self::LengthRequired,
+            412 => Self::PreconditionFailed,
+            413 => Self::PayloadTooLarge,
+            414 => Self::UriTooLong,
+            415 => Self::UnsupportedMediaType,
+            416 => Self::RangeNotSatisfiable,
+            417 => Self::ExpectationFailed,
+            426 => Self::UpgradeRequired,
+            428 => Self::PreconditionRequired,
+            429 => Self::TooManyRequests,
+            431 => Self::RequestHeaderFieldsTooLarge,
+            500 => Self::InternalServerError,
+            501 => Self::NotImplemented,
+            502 => Self::BadGateway,
+            503 => Self::ServiceUnavailable,
+            504 => Self::GatewayTimeout,
+            505 => Self::HttpVersionNotSupported,
+            511 => Self::NetworkAuthenticationRequired,
+            _ => Self::Other(code),
+        })
+    }
+
+    /// Returns the status code as bytes.
+    pub fn raw(self) -> &'static [u8; 3] {
+        // The digits of every code below 1000, which is all the well-formed ones.
+        static DIGITS: [[u8; 3]; 1000] = status_code_digits();
+        &DIGITS[usize::from(self.code()) % DIGITS.len()]
+    }
+
+    /// Parses a 3 digit status code from a byte slice.
+    ///
+    /// # Errors
+    /// `InvalidStatusCode` is returned if `bytes` is not a 3 digit number.
     pub fn try_from(bytes: &[u8]) -> Result<Self, MessageError> {
-        match bytes {
-            b"100" => Ok(Self::Continue),
-            b"200" => Ok(Self::OK),
-            b"204" => Ok(Self::NoContent),
-            b"400" => Ok(Self::BadRequest),
-            b"404" => Ok(Self::NotFound),
-            b"500" => Ok(Self::InternalServerError),
-            b"501" => Ok(Self::NotImplemented),
-            _ => Err(MessageError::InvalidResponse(
-                ResponseError::InvalidStatusCode("Unsupported HTTP status code."),
-            )),
+        if bytes.len() != 3 || !bytes.iter().all(u8::is_ascii_digit) {
+            return Err(MessageError::InvalidResponse(
+                ResponseError::InvalidStatusCode("Status code must have 3 digits."),
+            ));
         }
+        Self::from_code(
+            bytes
+                .iter()
+                .fold(0, |code, digit| code * 10 + u16::from(digit - b'0')),
+        )
     }
 }
 
@@ -82,9 +354,13 @@ impl StatusLine {
         buf.write_all(self.http_version.raw())?;
         buf.write_all(&[SP])?;
         buf.write_all(self.status_code.raw())?;
-        if let Some(status_text) = &self.status_message {
+        let reason_phrase = match &self.status_message {
+            Some(status_text) => status_text.as_str(),
+            None => self.status_code.reason_phrase(),
+        };
+        if !reason_phrase.is_empty() {
             buf.write_all(&[SP])?;
-            buf.write_all(status_text.as_bytes())?;
+            buf.write_all(reason_phrase.as_bytes())?;
         }
         buf.write_all(&[CR, LF])?;
 
@@ -131,6 +407,77 @@ impl StatusLine {
     }
 }
 
+/// A response body which is produced incrementally from a `Read` source instead
+/// of being held in memory.
+///
+/// On HTTP/1.1 every piece read from the source is sent as a chunk using
+/// `Transfer-Encoding: chunked`. HTTP/1.0 has no chunked transfer coding, so the
+/// bytes are sent as they are and the end of the body is marked by closing the
+/// connection.
+pub struct StreamingBody {
+    /// The source of the body bytes.
+    source: Box<dyn Read + Send>,
+    /// Whether the body is sent using the `chunked` transfer coding.
+    chunked: bool,
+    /// Whether the source has been exhausted and the end of the body was produced.
+    finished: bool,
+}
+
+impl StreamingBody {
+    fn new(source: Box<dyn Read + Send>, chunked: bool) -> Self {
+        Self {
+            source,
+            chunked,
+            finished: false,
+        }
+    }
+
+    /// Reads the next piece of the body from the source and appends it to `buf`,
+    /// framed according to the transfer coding of the body.
+    ///
+    /// Once the source is exhausted, the last chunk is appended and the body is
+    /// marked as finished.
+    ///
+    /// # Errors
+    /// Any error returned by the source is propagated. `WouldBlock` can be retried.
+    pub fn read_into(&mut self, buf: &mut Vec<u8>) -> Result<(), WriteError> {
+        let mut piece = [0u8; STREAM_CHUNK_SIZE];
+        let bytes_read = loop {
+            match self.source.read(&mut piece) {
+                Ok(bytes_read) => break bytes_read,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        };
+
+        if bytes_read == 0 {
+            self.finished = true;
+        }
+        if self.chunked {
+            write_chunk(buf, &piece[..bytes_read])?;
+        } else {
+            buf.extend_from_slice(&piece[..bytes_read]);
+        }
+        Ok(())
+    }
+
+    /// Returns `true` once the whole body has been produced.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+}
+
+/// Writes `data` as a single chunk of a chunked body. An empty `data` slice
+/// produces the last chunk, followed by an empty trailer section, which ends the body.
+fn write_chunk<T: Write>(buf: &mut T, data: &[u8]) -> Result<(), WriteError> {
+    buf.write_all(format!("{:X}", data.len()).as_bytes())?;
+    buf.write_all(&[CR, LF])?;
+    buf.write_all(data)?;
+    buf.write_all(&[CR, LF])?;
+
+    Ok(())
+}
+
 /// Wrapper over an HTTP Response.
 ///
 /// The Response is created using a `Version` and a `StatusCode`. When creating a Response object,
@@ -141,33 +488,35 @@ pub struct Response {
     status_line: StatusLine,
     headers: Headers,
     body: Option<Body>,
+    body_stream: Option<Box<dyn Read + Send>>,
+    /// Whether the body is left out when sending the response, as it is for
+    /// responses to HEAD requests.
+    body_omitted: bool,
 }
 
 impl Message for Response {
     fn send<U: Write>(&mut self, out: &mut U) -> Result<(), WriteError> {
-        let mut content_length: i32 = 0;
-        if let Some(body) = self.body() {
-            content_length = body.len() as i32;
-        }
-        self.headers.set_content_length(content_length);
-
-        self.status_line.write_all(out)?;
-        self.headers.write_all(out)?;
-        match self.body.as_mut() {
-            Some(body) => {
-                let mut slice: &[u8] = body.as_stream().as_mut_slice();
-                std::io::copy(&mut slice, out)?;
+        if let Some(mut body_stream) = self.send_head(out)? {
+            let mut buf = Vec::new();
+            while !body_stream.is_finished() {
+                body_stream.read_into(&mut buf)?;
+                out.write_all(&buf)?;
+                buf.clear();
             }
-            None => {}
         }
         Ok(())
     }
 
-    fn header_line(&self, key: &String) -> Option<&String> {
+    fn header_line(&self, key: &str) -> Option<&String> {
         self.headers.header_line(key)
     }
 
     fn with_header(&mut self, key: String, value: String) -> &mut Self {
+        self.headers.set_header_line(key, value);
+        self
+    }
+
+    fn add_header(&mut self, key: String, value: String) -> &mut Self {
         self.headers.add_header_line(key, value);
         self
     }
@@ -187,6 +536,7 @@ impl Message for Response {
     fn with_body(&mut self, bytes: &[u8]) -> &mut Self {
         self.headers.set_content_length(bytes.len() as i32);
         self.body = Some(Body::new(bytes));
+        self.body_stream = None;
         self
     }
 }
@@ -198,7 +548,113 @@ impl Response {
             status_line: StatusLine::new(http_version, status_code),
             headers: Headers::default(),
             body: Default::default(),
+            body_stream: None,
+            body_omitted: false,
+        }
+    }
+
+    /// Sets a body which is read incrementally from `source` while the response
+    /// is being sent, replacing any body set with `with_body`.
+    ///
+    /// The source is drained until it returns `0` bytes. It is read on the thread
+    /// that sends the response, so it should not block for long. A source which
+    /// has no data yet can return `WouldBlock` instead, in which case the server
+    /// reads it again once woken up through `ResponseSender::wake`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// extern crate micro_http;
+    /// use micro_http::{Message, Response, StatusCode, Version};
+    ///
+    /// let mut response = Response::new(Version::Http11, StatusCode::OK);
+    /// response.with_body_stream(std::io::Cursor::new(b"streamed".to_vec()));
+    ///
+    /// let mut response_buf = Vec::new();
+    /// response.send(&mut response_buf).unwrap();
+    /// assert_eq!(
+    ///     &response_buf[..],
+    ///     &b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n8\r\nstreamed\r\n0\r\n\r\n"[..]
+    /// );
+    /// ```
+    pub fn with_body_stream<R: Read + Send + 'static>(&mut self, source: R) -> &mut Self {
+        self.body = None;
+        self.body_stream = Some(Box::new(source));
+        self
+    }
+
+    /// Leaves the body out when sending the response, while keeping the headers
+    /// which describe it, such as `Content-Length`.
+    ///
+    /// This is how responses to HEAD requests are sent.
+    pub fn with_omitted_body(&mut self) -> &mut Self {
+        self.body_omitted = true;
+        self
+    }
+
+    /// Asks for the connection to be closed once this response is sent, by adding
+    /// the `Connection: close` header.
+    pub fn with_connection_close(&mut self) -> &mut Self {
+        self.headers
+            .set_header_line("Connection".to_string(), "close".to_string());
+        self
+    }
+
+    /// Returns `true` if the connection is closed once this response is sent.
+    pub fn connection_close(&self) -> bool {
+        self.headers.contains_token("Connection", "close")
+    }
+
+    /// Returns `true` if the body of the response is streamed from a `Read` source.
+    pub fn is_streaming(&self) -> bool {
+        self.body_stream.is_some()
+    }
+
+    /// Writes the status line and the headers of the response, adding the headers
+    /// which describe how the body is framed.
+    ///
+    /// A body set with `with_body` is written along with them. A streaming body is
+    /// returned instead, so that the caller can send it incrementally.
+    pub(crate) fn send_head<U: Write>(
+        &mut self,
+        out: &mut U,
+    ) -> Result<Option<StreamingBody>, WriteError> {
+        let body_stream = match self.body_stream.take() {
+            Some(source) => {
+                let chunked = self.status_line.http_version == Version::Http11;
+                self.headers.set_content_length(0);
+                self.headers.set_chunked(chunked);
+                if self.body_omitted {
+                    // The body is never read, so it has no end to be marked.
+                    None
+                } else {
+                    if !chunked {
+                        // Without chunking, the only way to mark the end of the body is
+                        // to close the connection.
+                        self.with_connection_close();
+                    }
+                    Some(StreamingBody::new(source, chunked))
+                }
+            }
+            None => {
+                let mut content_length: i32 = 0;
+                if let Some(body) = self.body() {
+                    content_length = body.len() as i32;
+                }
+                self.headers.set_content_length(content_length);
+                None
+            }
+        };
+
+        self.status_line.write_all(out)?;
+        self.headers.write_all(out)?;
+        if let Some(body) = self.body.as_mut() {
+            if !self.body_omitted {
+                let mut slice: &[u8] = body.as_stream().as_mut_slice();
+                std::io::copy(&mut slice, out)?;
+            }
         }
+        Ok(body_stream)
     }
 
     /// Returns the Status Code of the Response.
@@ -230,13 +686,13 @@ impl Response {
             n => {
                 if let Some(status_end) = find(&buf[..], &[CR, LF]) {
                     let headers_and_body = &buf[(status_end + CRLF_LEN)..n];
-                    if let Some(headers_end) =
-                        find(&headers_and_body[..], &[CR, LF, CR, LF])
-                    {
+                    if let Some(headers_end) = find(headers_and_body, &[CR, LF, CR, LF]) {
                         let mut response = Response {
                             status_line: StatusLine::try_from(&buf[..status_end])?,
                             headers: Headers::try_from(&headers_and_body[..headers_end])?,
                             body: Default::default(),
+                            body_stream: None,
+                            body_omitted: false,
                         };
 
                         if response.headers.content_length() != 0 {
@@ -245,7 +701,7 @@ impl Response {
                             let mut body: Vec<u8> = Vec::with_capacity(bytes_left as usize);
                             let buffered_body_len = body_bytes.len() as i32;
                             bytes_left -= buffered_body_len;
-                            body.write_all(&body_bytes[..])
+                            body.write_all(body_bytes)
                                 .map_err(|_| MessageError::IOError)?;
 
                             if bytes_left > 0 {
@@ -0,0 +1,449 @@
+use common::message::Message;
+use common::net::{PeerAllowList, PeerInfo};
+use common::Method;
+use request::{percent_decode, Request};
+use response::{Response, StatusCode};
+use server::ErrorResponses;
+
+/// Signature of the functions which handle the requests matched by a `Router`.
+type Handler = Box<dyn Fn(&Request, &PathParams) -> Response + Send + Sync>;
+
+/// The parameters extracted from the path of a request by a `Router`.
+///
+/// A parameter is named by a path segment of the form `{name}` in the pattern of
+/// a route, and takes the value of the corresponding segment of the path.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PathParams {
+    params: Vec<(String, String)>,
+}
+
+impl PathParams {
+    /// Returns the value of the parameter called `name`, if the route has one.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(param_name, _)| param_name == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns the parameters in the order in which they appear in the path.
+    pub fn as_slice(&self) -> &[(String, String)] {
+        &self.params
+    }
+}
+
+/// A segment of a route pattern.
+#[derive(PartialEq)]
+enum Segment {
+    /// A segment which has to appear in the path as it is.
+    Literal(String),
+    /// A segment which matches any non-empty path segment, saved under this name.
+    Param(String),
+}
+
+/// The handlers of a path pattern, one for each method, along with the clients
+/// allowed to call them.
+struct Route {
+    segments: Vec<Segment>,
+    handlers: Vec<(Method, Handler)>,
+    rules: Vec<(Method, PeerAllowList)>,
+}
+
+impl Route {
+    /// Returns the parameters of the path split into the percent-decoded
+    /// `path_segments` if it matches the pattern of the route.
+    fn match_path(&self, path_segments: &[String]) -> Option<PathParams> {
+        if path_segments.len() != self.segments.len() {
+            return None;
+        }
+
+        let mut params = PathParams::default();
+        for (segment, path_segment) in self.segments.iter().zip(path_segments) {
+            match segment {
+                Segment::Literal(literal) if literal == path_segment => {}
+                Segment::Param(name) if !path_segment.is_empty() => {
+                    params.params.push((name.clone(), path_segment.clone()))
+                }
+                _ => return None,
+            }
+        }
+        Some(params)
+    }
+
+    /// Returns the handler of `method`, along with the method it was registered
+    /// for. HEAD requests fall back to the handler of GET, as the body of their
+    /// response is omitted anyway.
+    fn handler(&self, method: &Method) -> Option<(&Method, &Handler)> {
+        let find = |method: &Method| {
+            self.handlers
+                .iter()
+                .find(|(handler_method, _)| handler_method == method)
+                .map(|(handler_method, handler)| (handler_method, handler))
+        };
+        match method {
+            Method::Head => find(&Method::Head).or_else(|| find(&Method::Get)),
+            _ => find(method),
+        }
+    }
+
+    /// Returns `true` if `peer` may call the handler registered for `method`.
+    fn allows(&self, method: &Method, peer: Option<&PeerInfo>) -> bool {
+        match self
+            .rules
+            .iter()
+            .find(|(rule_method, _)| rule_method == method)
+        {
+            Some((_, allowed_peers)) => peer.is_some_and(|peer| allowed_peers.allows(peer)),
+            None => true,
+        }
+    }
+}
+
+/// Splits a path into its segments, leaving out the leading `/`.
+fn split_path(path: &str) -> std::str::Split<'_, char> {
+    path.strip_prefix('/').unwrap_or(path).split('/')
+}
+
+/// Dispatches requests to handlers by their method and path.
+///
+/// Routes are registered with a method and a path pattern, in which segments of
+/// the form `{name}` match any non-empty path segment. The values of these
+/// segments are handed to the handler as `PathParams`. Requests whose path
+/// matches no route are answered with `404 Not Found`, while those whose path
+/// matches a route registered for other methods are answered with
+/// `405 Method Not Allowed` and an `Allow` header listing these methods.
+///
+/// A path is split into segments before being percent-decoded, so an encoded
+/// `/` is part of a segment. The responses built by the router itself carry the
+/// server name and the error body set with `with_server_name` and
+/// `with_error_body_template`, like the ones the server sends on its own.
+///
+/// A `Router` plugs into `ServerRequest::process`, as in
+/// `server_request.process(|request| router.handle(request))`.
+///
+/// # Example
+///
+/// ```
+/// extern crate micro_http;
+/// use micro_http::{Message, Method, Request, Response, Router, StatusCode, Version};
+///
+/// let mut router = Router::new();
+/// router.route(Method::Get, "/vms/{id}/drives/{drive_id}", |_request, params| {
+///     let mut response = Response::new(Version::Http11, StatusCode::OK);
+///     response.with_body(params.get("drive_id").unwrap().as_bytes());
+///     response
+/// });
+///
+/// let request = Request::try_from(b"GET /vms/1/drives/rootfs HTTP/1.1\r\n\r\n").unwrap();
+/// let mut response = router.handle(&request);
+/// assert_eq!(response.status(), StatusCode::OK);
+/// assert_eq!(response.body().unwrap().as_slice(), b"rootfs");
+///
+/// let request = Request::try_from(b"GET /vms/1 HTTP/1.1\r\n\r\n").unwrap();
+/// assert_eq!(router.handle(&request).status(), StatusCode::NotFound);
+///
+/// let request = Request::try_from(b"PUT /vms/1/drives/rootfs HTTP/1.1\r\n\r\n").unwrap();
+/// let response = router.handle(&request);
+/// assert_eq!(response.status(), StatusCode::MethodNotAllowed);
+/// assert_eq!(response.header_line("Allow").unwrap(), "GET, HEAD");
+/// ```
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<Route>,
+    error_responses: ErrorResponses,
+}
+
+impl Router {
+    /// Creates a router without any routes.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the value of the `Server` header of the responses built by the router
+    /// itself, such as `404 Not Found`. No such header is sent if it is `None`.
+    pub fn with_server_name(&mut self, server_name: Option<String>) -> &mut Self {
+        self.error_responses.server_name = server_name;
+        self
+    }
+
+    /// Sets the body of the responses built by the router itself, in which every
+    /// `{message}` is replaced by the description of the error.
+    pub fn with_error_body_template(&mut self, template: String) -> &mut Self {
+        self.error_responses.body_template = template;
+        self
+    }
+
+    /// Registers `handler` for the requests with the method `method` whose path
+    /// matches `pattern`. A handler registered earlier for the same method and
+    /// pattern is replaced.
+    ///
+    /// When several patterns match a path, the first one registered wins.
+    pub fn route<F>(&mut self, method: Method, pattern: &str, handler: F) -> &mut Self
+    where
+        F: Fn(&Request, &PathParams) -> Response + Send + Sync + 'static,
+    {
+        let handlers = &mut self.route_mut(pattern).handlers;
+        handlers.retain(|(handler_method, _)| *handler_method != method);
+        handlers.push((method, Box::new(handler)));
+        self
+    }
+
+    /// Only lets the clients in `allowed_peers` call the handler registered for
+    /// `method` and `pattern`. The other clients are answered with `403 Forbidden`,
+    /// as are all clients when the request is handled without knowing its sender.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// extern crate micro_http;
+    /// use micro_http::{
+    ///     Method, PeerAllowList, PeerCredentials, PeerInfo, Request, Response, Router,
+    ///     StatusCode, Version,
+    /// };
+    ///
+    /// let mut root = PeerAllowList::new();
+    /// root.allow_uid(0);
+    /// let mut router = Router::new();
+    /// router
+    ///     .route(Method::Put, "/machine-config", |_request, _params| {
+    ///         Response::new(Version::Http11, StatusCode::NoContent)
+    ///     })
+    ///     .restrict(Method::Put, "/machine-config", root);
+    ///
+    /// let request = Request::try_from(b"PUT /machine-config HTTP/1.1\r\n\r\n").unwrap();
+    /// let peer = PeerInfo::Uds(PeerCredentials {
+    ///     pid: 1,
+    ///     uid: 1000,
+    ///     gid: 1000,
+    /// });
+    /// assert_eq!(
+    ///     router.handle_from(&request, &peer).status(),
+    ///     StatusCode::Forbidden
+    /// );
+    /// let peer = PeerInfo::Uds(PeerCredentials {
+    ///     pid: 1,
+    ///     uid: 0,
+    ///     gid: 0,
+    /// });
+    /// assert_eq!(
+    ///     router.handle_from(&request, &peer).status(),
+    ///     StatusCode::NoContent
+    /// );
+    /// ```
+    pub fn restrict(
+        &mut self,
+        method: Method,
+        pattern: &str,
+        allowed_peers: PeerAllowList,
+    ) -> &mut Self {
+        let rules = &mut self.route_mut(pattern).rules;
+        rules.retain(|(rule_method, _)| *rule_method != method);
+        rules.push((method, allowed_peers));
+        self
+    }
+
+    /// Returns the route of `pattern`, which is added if it does not exist yet.
+    fn route_mut(&mut self, pattern: &str) -> &mut Route {
+        let segments: Vec<Segment> = split_path(pattern)
+            .map(|segment| {
+                match segment
+                    .strip_prefix('{')
+                    .and_then(|segment| segment.strip_suffix('}'))
+                {
+                    Some(name) => Segment::Param(name.to_string()),
+                    None => Segment::Literal(segment.to_string()),
+                }
+            })
+            .collect();
+
+        let route_index = match self
+            .routes
+            .iter()
+            .position(|route| route.segments == segments)
+        {
+            Some(route_index) => route_index,
+            None => {
+                self.routes.push(Route {
+                    segments,
+                    handlers: Vec::new(),
+                    rules: Vec::new(),
+                });
+                self.routes.len() - 1
+            }
+        };
+        &mut self.routes[route_index]
+    }
+
+    /// Calls the handler which matches the method and path of `request` to obtain
+    /// the response, or builds the `404` or `405` response if there is none.
+    ///
+    /// As the sender of the request is not known, the requests for restricted
+    /// handlers are answered with `403 Forbidden`.
+    pub fn handle(&self, request: &Request) -> Response {
+        self.dispatch(request, None)
+    }
+
+    /// Same as `handle`, for a request sent by `peer`, which is checked against
+    /// the clients allowed to call the matching handler.
+    ///
+    /// The sender of a request handed out by the server is given by
+    /// `ServerRequest::peer`.
+    pub fn handle_from(&self, request: &Request, peer: &PeerInfo) -> Response {
+        self.dispatch(request, Some(peer))
+    }
+
+    fn dispatch(&self, request: &Request, peer: Option<&PeerInfo>) -> Response {
+        let path_segments = match split_path(request.uri().raw_path())
+            .map(|segment| percent_decode(segment.as_bytes(), false))
+            .collect::<Result<Vec<String>, _>>()
+        {
+            Ok(path_segments) => path_segments,
+            Err(_) => {
+                return self
+                    .error_responses
+                    .build(StatusCode::BadRequest, "The path can not be decoded.")
+            }
+        };
+        let method = request.method();
+        let mut allowed_methods: Vec<Method> = Vec::new();
+
+        for route in self.routes.iter() {
+            let params = match route.match_path(&path_segments) {
+                Some(params) => params,
+                None => continue,
+            };
+            if let Some((handler_method, handler)) = route.handler(&method) {
+                if !route.allows(handler_method, peer) {
+                    return self.error_responses.build(
+                        StatusCode::Forbidden,
+                        "The client is not allowed to access the path.",
+                    );
+                }
+                return handler(request, &params);
+            }
+            for (handler_method, _) in route.handlers.iter() {
+                if !allowed_methods.contains(handler_method) {
+                    allowed_methods.push(handler_method.clone());
+                }
+            }
+        }
+
+        if allowed_methods.is_empty() {
+            return self
+                .error_responses
+                .build(StatusCode::NotFound, "No route matches the path.");
+        }
+        if allowed_methods.contains(&Method::Get) && !allowed_methods.contains(&Method::Head) {
+            allowed_methods.push(Method::Head);
+        }
+        let allow = allowed_methods
+            .iter()
+            .map(|method| String::from_utf8_lossy(method.raw()))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let mut response = self.error_responses.build(
+            StatusCode::MethodNotAllowed,
+            "The method is not allowed for the path.",
+        );
+        response.with_header("Allow".to_string(), allow);
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::Version;
+
+    fn router() -> Router {
+        let mut router = Router::new();
+        router
+            .with_server_name(None)
+            .with_error_body_template("{message}".to_string())
+            .route(
+                Method::Get,
+                "/vms/{id}/drives/{drive_id}",
+                |_request, params| {
+                    let mut response = Response::new(Version::Http11, StatusCode::OK);
+                    let body = format!("{:?}", params.as_slice());
+                    response.with_body(body.as_bytes());
+                    response
+                },
+            )
+            .route(Method::Put, "/vms/{id}", |_request, _params| {
+                Response::new(Version::Http11, StatusCode::NoContent)
+            })
+            .route(Method::Delete, "/vms/{id}", |_request, _params| {
+                Response::new(Version::Http11, StatusCode::NoContent)
+            });
+        router
+    }
+
+    fn handle(router: &Router, request_line: &[u8]) -> Response {
+        router.handle(&Request::try_from(request_line).unwrap())
+    }
+
+    #[test]
+    fn test_path_params() {
+        let router = router();
+        let mut response = handle(&router, b"GET /vms/1/drives/rootfs HTTP/1.1\r\n\r\n");
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.body().unwrap().as_slice(),
+            &br#"[("id", "1"), ("drive_id", "rootfs")]"#[..]
+        );
+
+        // A parameter does not match an empty segment.
+        let response = handle(&router, b"GET /vms//drives/rootfs HTTP/1.1\r\n\r\n");
+        assert_eq!(response.status(), StatusCode::NotFound);
+    }
+
+    #[test]
+    fn test_not_found() {
+        let router = router();
+        for request_line in [
+            &b"GET /machine-config HTTP/1.1\r\n\r\n"[..],
+            &b"GET /vms/1/drives HTTP/1.1\r\n\r\n"[..],
+            &b"GET /vms/1/drives/rootfs/more HTTP/1.1\r\n\r\n"[..],
+        ]
+        .iter()
+        {
+            let mut response = handle(&router, request_line);
+            assert_eq!(response.status(), StatusCode::NotFound);
+            assert_eq!(
+                response.body().unwrap().as_slice(),
+                b"No route matches the path."
+            );
+            assert!(response.header_line("Server").is_none());
+        }
+    }
+
+    #[test]
+    fn test_method_not_allowed() {
+        let router = router();
+        let response = handle(&router, b"GET /vms/1 HTTP/1.1\r\n\r\n");
+        assert_eq!(response.status(), StatusCode::MethodNotAllowed);
+        assert_eq!(response.header_line("Allow").unwrap(), "PUT, DELETE");
+
+        // HEAD is allowed wherever GET is.
+        let response = handle(&router, b"POST /vms/1/drives/rootfs HTTP/1.1\r\n\r\n");
+        assert_eq!(response.status(), StatusCode::MethodNotAllowed);
+        assert_eq!(response.header_line("Allow").unwrap(), "GET, HEAD");
+        let response = handle(&router, b"HEAD /vms/1/drives/rootfs HTTP/1.1\r\n\r\n");
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn test_encoded_slash() {
+        let router = router();
+        // An encoded `/` is part of the segment instead of separating two of them.
+        let mut response = handle(&router, b"GET /vms/4%2F2/drives/root%20fs HTTP/1.1\r\n\r\n");
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.body().unwrap().as_slice(),
+            &br#"[("id", "4/2"), ("drive_id", "root fs")]"#[..]
+        );
+        let response = handle(&router, b"PUT /vms/4%2F2 HTTP/1.1\r\n\r\n");
+        assert_eq!(response.status(), StatusCode::NoContent);
+    }
+}
@@ -3,39 +3,336 @@ use std::net::ToSocketAddrs;
 use std::os::unix::io::AsRawFd;
 use std::os::unix::io::RawFd;
 use std::path::Path;
-
-use common::Version;
-pub use common::{ConnectionError, RequestError, ServerError};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use common::{MessageError, Method, RequestError, Version};
+pub use common::{ConnectionError, ServerError};
+use access_log::{AccessLog, AccessLogEntry, RequestSummary};
+use common::eventfd::EventFd;
 use common::message::Message;
-use common::net::{PollableListener, PollableStream};
-use connection::HttpConnection;
+use common::net::{PeerAllowList, PeerInfo, PollableListener, PollableStream};
+use common::timerfd::TimerFd;
+use connection::{HttpConnection, RequestLimits, BUFFER_SIZE};
+use metrics::ServerMetrics;
 use request::Request;
 use response::{Response, StatusCode};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use worker_pool::{WorkerHandler, WorkerPool};
 
 use common::epoll::{ControlOperation, Epoll, EPOLL_IN, EPOLL_OUT, EpollEvent, EventSet};
 
-static SERVER_FULL_ERROR_MESSAGE: &[u8] = b"HTTP/1.1 503\r\n\
-                                            Server: Firecracker API\r\n\
-                                            Connection: close\r\n\
-                                            Content-Length: 40\r\n\r\n{ \"error\": \"Too many open connections\" }";
 const MAX_CONNECTIONS: usize = 10;
+const SERVER_NAME: &str = "Firecracker API";
+const ERROR_BODY_TEMPLATE: &str = "{ \"error\": \"{message}\" }";
 
 type Result<T> = std::result::Result<T, ServerError>;
 
+/// What the server does with a client connecting while it is full.
+#[derive(Clone, Debug, PartialEq)]
+pub enum OverloadPolicy {
+    /// Answer the client with `503 Service Unavailable` and close its connection.
+    Reject,
+    /// Close the idle connection on which data was exchanged the longest time ago
+    /// to make room for the client. A connection is idle when it has no request
+    /// in progress and no response left to send. The client is rejected as with
+    /// `Reject` if no connection is idle.
+    EvictIdle,
+    /// Keep the last `connections` slots for the clients in `peers`. The other
+    /// clients are rejected as with `Reject` once only these slots are left, while
+    /// the clients in `peers` are only rejected when the server is full.
+    Reserve {
+        connections: usize,
+        peers: PeerAllowList,
+    },
+    /// Stop accepting connections until one of the open connections is closed.
+    /// The clients wait in the backlog of the listening socket in the meantime.
+    PauseAccept,
+}
+
+/// Configuration of an `HttpServer`.
+///
+/// # Example
+///
+/// ```
+/// use micro_http::{HttpServer, HttpServerConfig, RequestLimits};
+///
+/// let path_to_socket = "/tmp/example_config.sock";
+/// std::fs::remove_file(path_to_socket).unwrap_or_default();
+///
+/// let mut config = HttpServerConfig::default();
+/// config
+///     .with_max_connections(64)
+///     .with_request_limits(RequestLimits {
+///         max_body_len: 64 * 1024,
+///         ..Default::default()
+///     })
+///     .with_server_name(Some("Example".to_string()))
+///     .with_error_body_template("error: {message}\n".to_string());
+/// let mut server = HttpServer::new_uds_with_config(path_to_socket, config).unwrap();
+/// server.start_server().unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct HttpServerConfig {
+    /// Maximum number of connections open at the same time.
+    max_connections: usize,
+    /// Maximum number of `epoll` events handled by one `requests` call.
+    max_events: usize,
+    /// Initial size of the buffer in which the bytes received on a connection
+    /// are stored.
+    buffer_size: usize,
+    /// The limits enforced on the requests received by the server.
+    limits: RequestLimits,
+    /// How the responses sent by the server itself are built.
+    error_responses: ErrorResponses,
+    /// Maximum time a connection stays open while no request is in progress.
+    idle_timeout: Option<Duration>,
+    /// Maximum time in which the header section of a request has to be received,
+    /// starting from its first byte.
+    header_timeout: Option<Duration>,
+    /// Maximum time in which the body of a request has to be received, starting
+    /// from the end of its header section.
+    body_timeout: Option<Duration>,
+    /// The clients allowed to connect, checked when their connection is accepted.
+    allowed_peers: Option<PeerAllowList>,
+    /// What the server does with a client connecting while it is full.
+    overload_policy: OverloadPolicy,
+    /// Value of the `Retry-After` header of the responses rejecting a client
+    /// because the server is full.
+    retry_after: Option<Duration>,
+}
+
+impl Default for HttpServerConfig {
+    fn default() -> Self {
+        Self {
+            max_connections: MAX_CONNECTIONS,
+            max_events: MAX_CONNECTIONS,
+            buffer_size: BUFFER_SIZE,
+            limits: RequestLimits::default(),
+            error_responses: ErrorResponses::default(),
+            idle_timeout: None,
+            header_timeout: None,
+            body_timeout: None,
+            allowed_peers: None,
+            overload_policy: OverloadPolicy::Reject,
+            retry_after: None,
+        }
+    }
+}
+
+impl HttpServerConfig {
+    /// Sets the maximum number of connections open at the same time. Clients
+    /// connecting when the server is full are handled according to the overload
+    /// policy.
+    pub fn with_max_connections(&mut self, max_connections: usize) -> &mut Self {
+        self.max_connections = max_connections;
+        self
+    }
+
+    /// Sets the maximum number of `epoll` events handled by one `requests` call.
+    pub fn with_max_events(&mut self, max_events: usize) -> &mut Self {
+        // `epoll_wait` fails if it can not return at least one event.
+        self.max_events = std::cmp::max(max_events, 1);
+        self
+    }
+
+    /// Sets the initial size of the buffer in which the bytes received on a
+    /// connection are stored. The buffer grows when a line does not fit in it,
+    /// up to the request limits.
+    pub fn with_buffer_size(&mut self, buffer_size: usize) -> &mut Self {
+        self.buffer_size = buffer_size;
+        self
+    }
+
+    /// Sets the limits enforced on the requests received by the server.
+    pub fn with_request_limits(&mut self, limits: RequestLimits) -> &mut Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Sets the value of the `Server` header of the responses sent by the server
+    /// itself, such as error responses. No such header is sent if it is `None`.
+    pub fn with_server_name(&mut self, server_name: Option<String>) -> &mut Self {
+        self.error_responses.server_name = server_name;
+        self
+    }
+
+    /// Sets the body of the responses sent by the server itself, in which every
+    /// `{message}` is replaced by the description of the error.
+    pub fn with_error_body_template(&mut self, template: String) -> &mut Self {
+        self.error_responses.body_template = template;
+        self
+    }
+
+    /// Sets the maximum time a connection stays open while no request is in
+    /// progress and no response is being sent. The connection is then closed.
+    pub fn with_idle_timeout(&mut self, timeout: Option<Duration>) -> &mut Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Sets the maximum time in which the header section of a request has to be
+    /// received, starting from its first byte. The client is then answered with
+    /// `408 Request Timeout` and the connection is closed.
+    pub fn with_header_timeout(&mut self, timeout: Option<Duration>) -> &mut Self {
+        self.header_timeout = timeout;
+        self
+    }
+
+    /// Sets the maximum time in which the body of a request has to be received,
+    /// starting from the end of its header section. The client is then answered
+    /// with `408 Request Timeout` and the connection is closed.
+    pub fn with_body_timeout(&mut self, timeout: Option<Duration>) -> &mut Self {
+        self.body_timeout = timeout;
+        self
+    }
+
+    /// Sets the clients allowed to connect to the server, which are checked when
+    /// their connection is accepted. The other clients are answered with
+    /// `403 Forbidden` and their connection is closed.
+    ///
+    /// As only the peers of Unix domain sockets have credentials, no TCP client is
+    /// allowed if a list is set.
+    pub fn with_allowed_peers(&mut self, allowed_peers: Option<PeerAllowList>) -> &mut Self {
+        self.allowed_peers = allowed_peers;
+        self
+    }
+
+    /// Sets what the server does with a client connecting while it is full. By
+    /// default, the client is answered with `503 Service Unavailable`.
+    pub fn with_overload_policy(&mut self, overload_policy: OverloadPolicy) -> &mut Self {
+        self.overload_policy = overload_policy;
+        self
+    }
+
+    /// Sets the value of the `Retry-After` header of the responses rejecting a
+    /// client because the server is full, rounded up to whole seconds. No such
+    /// header is sent if it is `None`.
+    pub fn with_retry_after(&mut self, retry_after: Option<Duration>) -> &mut Self {
+        self.retry_after = retry_after;
+        self
+    }
+
+    /// Builds a response sent by the server itself to report an error.
+    pub(crate) fn error_response(&self, status_code: StatusCode, message: &str) -> Response {
+        self.error_responses.build(status_code, message)
+    }
+}
+
+/// The parts of the configuration used to build the responses which report an
+/// error, shared by the server and `Router`.
+#[derive(Clone, Debug)]
+pub(crate) struct ErrorResponses {
+    /// Value of the `Server` header of the responses.
+    pub(crate) server_name: Option<String>,
+    /// Body of the responses, in which `{message}` is replaced by the description
+    /// of the error.
+    pub(crate) body_template: String,
+}
+
+impl Default for ErrorResponses {
+    fn default() -> Self {
+        Self {
+            server_name: Some(SERVER_NAME.to_string()),
+            body_template: ERROR_BODY_TEMPLATE.to_string(),
+        }
+    }
+}
+
+impl ErrorResponses {
+    /// Builds a response reporting an error described by `message`.
+    pub(crate) fn build(&self, status_code: StatusCode, message: &str) -> Response {
+        let mut response = Response::new(Version::Http11, status_code);
+        if let Some(server_name) = self.server_name.as_ref() {
+            response.with_header("Server".to_string(), server_name.clone());
+        }
+        response.with_body(self.body_template.replace("{message}", message).as_bytes());
+        response
+    }
+}
+
+/// Opaque identification token of a request received by the server.
+///
+/// It identifies both the connection on which the request was received and
+/// the position of the request on it, so a response can never be delivered
+/// to a different client, even if the file descriptor of the connection is
+/// reused after it is closed.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct RequestId {
+    /// The file descriptor of the stream of the connection.
+    fd: RawFd,
+    /// Tells apart the connections that were given the same file descriptor.
+    generation: u64,
+    /// The position of the request among the ones received on the connection.
+    sequence: u64,
+}
+
+impl RequestId {
+    /// Returns the position of the request among the ones received on its
+    /// connection, starting from `0`.
+    ///
+    /// Responses are sent in this order, regardless of the order in which they
+    /// are handed to the server.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+}
+
+/// Identification token of a socket on which an `HttpServer` listens for
+/// connections.
+///
+/// The listener the server is created with has the index `0`, and each listener
+/// added afterwards gets the next index.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct ListenerId(usize);
+
+impl ListenerId {
+    /// Returns the position of the listener among the ones of the server.
+    pub fn index(&self) -> usize {
+        self.0
+    }
+}
+
 /// Wrapper over `Request` which adds an identification token.
 pub struct ServerRequest {
     /// Inner request.
     pub request: Request,
     /// Identification token.
-    id: u64,
+    id: RequestId,
+    /// Information about the client which sent the request.
+    peer: PeerInfo,
+    /// The listener which accepted the connection of the request.
+    listener: ListenerId,
 }
 
 impl ServerRequest {
     /// Creates a new `ServerRequest` object from an existing `Request`,
-    /// adding an identification token.
-    pub fn new(request: Request, id: u64) -> Self {
-        Self { request, id }
+    /// adding an identification token, the information about its sender and
+    /// the listener on which it came in.
+    pub fn new(request: Request, id: RequestId, peer: PeerInfo, listener: ListenerId) -> Self {
+        Self {
+            request,
+            id,
+            peer,
+            listener,
+        }
+    }
+
+    /// Returns the identification token of the request.
+    pub fn id(&self) -> RequestId {
+        self.id
+    }
+
+    /// Returns the information about the client which sent the request: its
+    /// address for TCP connections and its credentials for Unix domain sockets.
+    pub fn peer(&self) -> PeerInfo {
+        self.peer
+    }
+
+    /// Returns the listener which accepted the connection of the request.
+    pub fn listener(&self) -> ListenerId {
+        self.listener
     }
 
     /// Returns a reference to the inner request.
@@ -46,12 +343,18 @@ impl ServerRequest {
     /// Calls the function provided on the inner request to obtain the response.
     /// The response is then wrapped in a `ServerResponse`.
     ///
+    /// The body of a response to a HEAD request is omitted, so the same function
+    /// can be used to handle both GET and HEAD requests.
+    ///
     /// Returns a `ServerResponse` ready for yielding to the server
     pub fn process<F>(&self, callable: F) -> ServerResponse
     where
         F: Fn(&Request) -> Response,
     {
-        let http_response = callable(self.inner());
+        let mut http_response = callable(self.inner());
+        if self.request.method() == Method::Head {
+            http_response.with_omitted_body();
+        }
         ServerResponse::new(http_response, self.id)
     }
 }
@@ -60,14 +363,85 @@ impl ServerRequest {
 pub struct ServerResponse {
     /// Inner response.
     response: Response,
-    /// Identification token.
-    id: u64,
+    /// Identification token of the request which is answered.
+    id: RequestId,
 }
 
 impl ServerResponse {
-    fn new(response: Response, id: u64) -> Self {
+    fn new(response: Response, id: RequestId) -> Self {
         Self { response, id }
     }
+
+    /// Returns the identification token of the request which is answered.
+    pub fn id(&self) -> RequestId {
+        self.id
+    }
+}
+
+/// Handle through which any thread hands responses to an `HttpServer` or
+/// interrupts its wait for events.
+///
+/// Both wake up the server if it is blocked in `requests`, which then returns so
+/// that the caller can call it again to send the responses. Handles are obtained
+/// with `HttpServer::response_sender` and can be cloned and moved across threads.
+///
+/// # Example
+///
+/// ```
+/// extern crate micro_http;
+/// use micro_http::{HttpServer, Response, StatusCode, Version};
+///
+/// let path_to_socket = "/tmp/example_response_sender.sock";
+/// std::fs::remove_file(path_to_socket).unwrap_or_default();
+///
+/// let mut server = HttpServer::new_uds(path_to_socket).unwrap();
+/// server.start_server().unwrap();
+///
+/// let response_sender = server.response_sender();
+/// let waker = response_sender.clone();
+/// std::thread::spawn(move || waker.wake().unwrap());
+///
+/// // Returns once the other thread wakes up the server.
+/// for request in server.requests().unwrap() {
+///     let response_sender = response_sender.clone();
+///     std::thread::spawn(move || {
+///         let response =
+///             request.process(|_request| Response::new(Version::Http11, StatusCode::NoContent));
+///         response_sender.send(response).unwrap();
+///     });
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct ResponseSender {
+    responses: Sender<ServerResponse>,
+    /// Becomes readable when the server has to be woken up.
+    event_fd: Arc<EventFd>,
+}
+
+impl ResponseSender {
+    /// Hands `response` to the server, which enqueues it the next time it wakes
+    /// up. A response whose connection no longer exists or whose request was
+    /// already answered is dropped, as with `HttpServer::enqueue_responses`.
+    ///
+    /// # Errors
+    /// `ServerGone` is returned when the server no longer exists.
+    /// `IOError` is returned when the server can not be woken up.
+    pub fn send(&self, response: ServerResponse) -> Result<()> {
+        self.responses
+            .send(response)
+            .map_err(|_| ServerError::ServerGone)?;
+        self.wake()
+    }
+
+    /// Makes the server return from the `requests` call in which it waits for
+    /// events, or from the next one if it is not waiting. The sources of the
+    /// streaming bodies which returned `WouldBlock` are then read again.
+    ///
+    /// # Errors
+    /// `IOError` is returned when the server can not be woken up.
+    pub fn wake(&self) -> Result<()> {
+        self.event_fd.notify().map_err(ServerError::IOError)
+    }
 }
 
 /// Describes the state of the connection as far as data exchange
@@ -79,6 +453,15 @@ enum ClientConnectionState {
     Closed,
 }
 
+/// The part of a request which a connection is receiving, which tells the
+/// timeout that applies to it.
+#[derive(Clone, Copy, PartialEq)]
+enum ReadPhase {
+    Idle,
+    Headers,
+    Body,
+}
+
 /// Wrapper over `HttpConnection` which keeps track of yielded
 /// requests and absorbed responses.
 struct ClientConnection<T> {
@@ -90,24 +473,85 @@ struct ClientConnection<T> {
     /// absorbed responses.
     /// This has to be `0` if we want to drop the connection.
     in_flight_response_count: u32,
+    /// The file descriptor of the stream of the connection.
+    fd: RawFd,
+    /// The number of connections accepted by the server before this one.
+    generation: u64,
+    /// The sequence number given to the next request received.
+    next_sequence: u64,
+    /// Responses which can not be sent yet because some of the requests received
+    /// before the ones they answer are still unanswered, keyed by sequence number.
+    held_responses: BTreeMap<u64, Response>,
+    /// The sequence number of the request whose response is sent next.
+    next_response_sequence: u64,
+    /// The part of a request which the connection is receiving.
+    read_phase: ReadPhase,
+    /// The moment at which the connection entered `read_phase`.
+    phase_start: Instant,
+    /// The moment at which data was last exchanged on the connection.
+    last_activity: Instant,
+    /// Information about the client on the other end of the connection.
+    peer: PeerInfo,
+    /// The listener which accepted the connection.
+    listener: ListenerId,
+    /// The requests whose responses were not sent yet, in the order in which they
+    /// were received, along with the moment at which they were received. Responses
+    /// sent by the server on its own have no request.
+    unsent_requests: VecDeque<(Option<RequestSummary>, SystemTime, Instant)>,
+}
+
+/// Returns the status code of the response sent for a request which failed to parse.
+fn error_status(error: &MessageError) -> StatusCode {
+    match error {
+        MessageError::InvalidRequest(RequestError::RequestLineTooLong) => StatusCode::UriTooLong,
+        MessageError::InvalidRequest(RequestError::HeaderFieldsTooLarge(_)) => {
+            StatusCode::RequestHeaderFieldsTooLarge
+        }
+        MessageError::InvalidRequest(RequestError::PayloadTooLarge) => StatusCode::PayloadTooLarge,
+        _ => StatusCode::BadRequest,
+    }
 }
 
 impl<T: Read + Write> ClientConnection<T> {
-    fn new(connection: HttpConnection<T>) -> Self {
+    fn new(
+        connection: HttpConnection<T>,
+        fd: RawFd,
+        generation: u64,
+        peer: PeerInfo,
+        listener: ListenerId,
+    ) -> Self {
         Self {
             connection,
             state: ClientConnectionState::AwaitingIncoming,
             in_flight_response_count: 0,
+            fd,
+            generation,
+            next_sequence: 0,
+            held_responses: BTreeMap::new(),
+            next_response_sequence: 0,
+            read_phase: ReadPhase::Idle,
+            phase_start: Instant::now(),
+            last_activity: Instant::now(),
+            peer,
+            listener,
+            unsent_requests: VecDeque::new(),
         }
     }
 
-    fn read(&mut self) -> Result<Vec<Request>> {
+    fn read(
+        &mut self,
+        config: &HttpServerConfig,
+        metrics: &mut ServerMetrics,
+    ) -> Result<Vec<ServerRequest>> {
         // Data came into the connection.
         let mut parsed_requests = vec![];
         match self.connection.try_read() {
             Err(ConnectionError::ConnectionClosed) => {
                 // Connection timeout.
                 self.state = ClientConnectionState::Closed;
+                // A response whose streaming body was waiting for data can no
+                // longer be delivered.
+                self.connection.clear_write_buffer();
                 // We don't want to propagate this to the server and we will
                 // return no requests and wait for the connection to become
                 // safe to drop.
@@ -116,23 +560,25 @@ impl<T: Read + Write> ClientConnection<T> {
             Err(ConnectionError::StreamError(inner)) => {
                 // Reading from the connection failed.
                 // We should try to write an error message regardless.
-                let mut internal_error_response =
-                    Response::new(Version::Http11, StatusCode::InternalServerError);
-                internal_error_response.with_body(inner.to_string().as_bytes());
-                self.connection.enqueue_response(internal_error_response);
+                let internal_error_response =
+                    config.error_response(StatusCode::InternalServerError, &inner.to_string());
+                self.enqueue_error_response(internal_error_response);
             }
             Err(ConnectionError::ParseError(inner)) => {
                 // An error occurred while parsing the read bytes.
                 // Check if there are any valid parsed requests in the queue.
                 while let Some(_discarded_request) = self.connection.pop_parsed_request() {}
+                metrics.record_parse_error(&inner);
 
                 // Send an error response for the request that gave us the error.
-                let mut error_response = Response::new(Version::Http11, StatusCode::BadRequest);
-                error_response.with_body(format!(
-                    "{{ \"error\": \"{}\nAll previous unanswered requests will be dropped.\" }}",
-                    inner.to_string()
-                ).as_bytes());
-                self.connection.enqueue_response(error_response);
+                let error_response = config.error_response(
+                    error_status(&inner),
+                    &format!(
+                        "{}\nAll previous unanswered requests will be dropped.",
+                        inner
+                    ),
+                );
+                self.enqueue_error_response(error_response);
             }
             Err(ConnectionError::InvalidWrite) => {
                 // This is unreachable because `HttpConnection::try_read()` cannot return this error variant.
@@ -140,8 +586,20 @@ impl<T: Read + Write> ClientConnection<T> {
             }
             Ok(()) => {
                 while let Some(request) = self.connection.pop_parsed_request() {
-                    // Add all valid requests to `parsed_requests`.
-                    parsed_requests.push(request);
+                    // Add all valid requests to `parsed_requests`, along with the
+                    // token which identifies them.
+                    let id = RequestId {
+                        fd: self.fd,
+                        generation: self.generation,
+                        sequence: self.next_sequence,
+                    };
+                    self.next_sequence += 1;
+                    self.unsent_requests.push_back((
+                        Some(RequestSummary::new(&request)),
+                        SystemTime::now(),
+                        Instant::now(),
+                    ));
+                    parsed_requests.push(ServerRequest::new(request, id, self.peer, self.listener));
                 }
             }
         }
@@ -170,18 +628,156 @@ impl<T: Read + Write> ClientConnection<T> {
             _ => {
                 // Check if we still have bytes to write for this connection.
                 if !self.connection.pending_write() {
-                    self.state = ClientConnectionState::AwaitingIncoming;
+                    if self.connection.close_after_write() {
+                        // The end of the last response is marked by closing the connection.
+                        self.state = ClientConnectionState::Closed;
+                    } else {
+                        self.state = ClientConnectionState::AwaitingIncoming;
+                    }
                 }
             }
         }
         Ok(())
     }
 
-    fn enqueue_response(&mut self, response: Response) {
-        if self.state != ClientConnectionState::Closed {
-            self.connection.enqueue_response(response);
+    fn enqueue_response(&mut self, sequence: u64, response: Response) -> Result<()> {
+        if sequence >= self.next_sequence
+            || sequence < self.next_response_sequence
+            || self.held_responses.contains_key(&sequence)
+        {
+            // The request was never received or it was already answered.
+            return Err(ServerError::AlreadyAnswered);
         }
         self.in_flight_response_count -= 1;
+        self.hold_response(sequence, response);
+        Ok(())
+    }
+
+    // Sends a response for a request which failed and was not handed to the user,
+    // after the responses to the requests received before it.
+    fn enqueue_error_response(&mut self, response: Response) {
+        let sequence = self.next_sequence;
+        self.next_sequence += 1;
+        self.unsent_requests
+            .push_back((None, SystemTime::now(), Instant::now()));
+        self.hold_response(sequence, response);
+    }
+
+    // Returns the responses completely sent since the last call, along with the
+    // requests they answer.
+    fn sent_responses(&mut self) -> Vec<AccessLogEntry> {
+        let mut entries = Vec::new();
+        while let Some(sent_response) = self.connection.pop_sent_response() {
+            // Responses are sent in the order in which the requests were received.
+            let (request, time, received) = match self.unsent_requests.pop_front() {
+                Some(unsent_request) => unsent_request,
+                None => break,
+            };
+            entries.push(AccessLogEntry {
+                peer: self.peer,
+                request,
+                time,
+                status_code: sent_response.status_code,
+                response_len: sent_response.len,
+                latency: received.elapsed(),
+            });
+        }
+        entries
+    }
+
+    // Holds the response back until all the requests received before the one it
+    // answers are answered, then moves the responses that are ready to be sent,
+    // in order, to the connection.
+    fn hold_response(&mut self, sequence: u64, response: Response) {
+        self.held_responses.insert(sequence, response);
+        while let Some(response) = self.held_responses.remove(&self.next_response_sequence) {
+            if self.state != ClientConnectionState::Closed {
+                self.connection.enqueue_response(response);
+            }
+            self.next_response_sequence += 1;
+        }
+    }
+
+    // Records that data was exchanged on the connection at `now`, which may have
+    // moved it to a different part of a request.
+    fn update_phase(&mut self, now: Instant) {
+        self.last_activity = now;
+        let read_phase = if self.connection.receiving_headers() {
+            ReadPhase::Headers
+        } else if self.connection.receiving_body() {
+            ReadPhase::Body
+        } else {
+            ReadPhase::Idle
+        };
+        if read_phase != self.read_phase {
+            self.read_phase = read_phase;
+            self.phase_start = now;
+        }
+    }
+
+    // Returns the moment at which the connection times out, if any.
+    fn deadline(&self, config: &HttpServerConfig) -> Option<Instant> {
+        if self.state == ClientConnectionState::Closed {
+            return None;
+        }
+        match self.read_phase {
+            ReadPhase::Headers => config
+                .header_timeout
+                .map(|timeout| self.phase_start + timeout),
+            ReadPhase::Body => config
+                .body_timeout
+                .map(|timeout| self.phase_start + timeout),
+            // The connection is not idle while it has requests to answer.
+            ReadPhase::Idle
+                if self.in_flight_response_count == 0 && !self.connection.pending_write() =>
+            {
+                config
+                    .idle_timeout
+                    .map(|timeout| self.last_activity + timeout)
+            }
+            ReadPhase::Idle => None,
+        }
+    }
+
+    // Closes the connection after its deadline expired. A request that is only
+    // partially received is answered with `408 Request Timeout` first.
+    fn time_out(&mut self, config: &HttpServerConfig) {
+        if self.read_phase == ReadPhase::Idle {
+            self.state = ClientConnectionState::Closed;
+            return;
+        }
+
+        self.connection.discard_incoming();
+        self.read_phase = ReadPhase::Idle;
+        let mut timeout_response =
+            config.error_response(StatusCode::RequestTimeout, "Request timed out.");
+        timeout_response.with_connection_close();
+        self.enqueue_error_response(timeout_response);
+        if self.connection.pending_write() {
+            self.state = ClientConnectionState::AwaitingOutgoing;
+        }
+    }
+
+    // Stops reading requests from the connection, which is closed right away if
+    // there are no responses left to send.
+    fn shut_down(&mut self) {
+        if self.state == ClientConnectionState::Closed {
+            return;
+        }
+        self.connection.close_after_responses();
+        self.read_phase = ReadPhase::Idle;
+        if self.in_flight_response_count == 0 && !self.connection.pending_write() {
+            self.state = ClientConnectionState::Closed;
+        }
+    }
+
+    /// Returns `true` if the connection is open, has no request in progress and
+    /// no response left to send.
+    fn is_idle(&self) -> bool {
+        self.state == ClientConnectionState::AwaitingIncoming
+            && self.read_phase == ReadPhase::Idle
+            && self.in_flight_response_count == 0
+            && !self.connection.pending_write()
     }
 
     // Returns `true` if the connection is closed and safe to drop.
@@ -236,8 +832,8 @@ impl<T: Read + Write> ClientConnection<T> {
 /// }
 /// ```
 pub struct HttpServer {
-    /// Socket on which we listen for new connections.
-    socket: PollableListener,
+    /// Sockets on which we listen for new connections, indexed by `ListenerId`.
+    listeners: Vec<PollableListener>,
     /// Server's epoll instance.
     epoll: Epoll,
     /// Holds the token-connection pairs of the server.
@@ -246,104 +842,262 @@ pub struct HttpServer {
     /// We use the file descriptor of the stream as the key for mapping
     /// connections because the 1-to-1 relation is guaranteed by the OS.
     connections: HashMap<RawFd, ClientConnection<PollableStream>>,
+    /// The number of connections accepted so far, used to tell apart the
+    /// connections which were given the same file descriptor.
+    connection_count: u64,
+    /// The connections evicted while handling the current batch of events. They
+    /// are dropped once the batch is handled, so that their file descriptors are
+    /// not given to new connections while events for them may still be pending.
+    evicted_connections: Vec<ClientConnection<PollableStream>>,
+    /// The configuration of the server.
+    config: HttpServerConfig,
+    /// Timer which expires at the earliest deadline of the connections.
+    timer: TimerFd,
+    /// Whether the server is shutting down, in which case it no longer accepts
+    /// connections nor parses requests.
+    shutting_down: bool,
+    /// The moment at which the connections still open during shutdown are dropped.
+    shutdown_deadline: Option<Instant>,
+    /// The log to which every response sent is written, if any.
+    access_log: Option<AccessLog>,
+    /// The counters kept about the connections and requests served.
+    metrics: ServerMetrics,
+    /// Whether the listening sockets were left out of the `epoll` structure
+    /// because the server is full.
+    accept_paused: bool,
+    /// Whether `start_server` was called.
+    started: bool,
+    /// Hands the responses produced on other threads to the server.
+    response_sender: ResponseSender,
+    /// Receives the responses sent through `response_sender`.
+    response_receiver: Receiver<ServerResponse>,
+    /// The threads to which the requests are handed, if any.
+    workers: Option<WorkerPool>,
 }
 
 impl HttpServer {
-    /// Constructor for `HttpServer` on a TCP socket.
+    /// Constructor for `HttpServer` on a TCP socket, using the default configuration.
     ///
     /// Returns the newly formed `HttpServer`.
     ///
     /// # Errors
     /// Returns an `IOError` when binding or `epoll::create` fails.
     pub fn new_tcp<A: ToSocketAddrs>(addr: A) -> Result<Self> {
+        Self::new_tcp_with_config(addr, HttpServerConfig::default())
+    }
+
+    /// Constructor for `HttpServer` on a TCP socket, using the provided configuration.
+    ///
+    /// Returns the newly formed `HttpServer`.
+    ///
+    /// # Errors
+    /// Returns an `IOError` when binding or `epoll::create` fails.
+    pub fn new_tcp_with_config<A: ToSocketAddrs>(
+        addr: A,
+        config: HttpServerConfig,
+    ) -> Result<Self> {
         let socket = PollableListener::bind_tcp(addr).map_err(ServerError::IOError)?;
-        let epoll = Epoll::new().map_err(ServerError::IOError)?;
-        Ok(Self {
-            socket,
-            epoll,
-            connections: HashMap::new(),
-        })
+        Self::new(socket, config)
     }
 
-    /// Constructor for `HttpServer` on a Unix Domain Socket.
+    /// Constructor for `HttpServer` on a Unix Domain Socket, using the default
+    /// configuration.
     ///
     /// Returns the newly formed `HttpServer`.
     ///
     /// # Errors
     /// Returns an `IOError` when binding or `epoll::create` fails.
     pub fn new_uds<P: AsRef<Path>>(path_to_socket: P) -> Result<Self> {
+        Self::new_uds_with_config(path_to_socket, HttpServerConfig::default())
+    }
+
+    /// Constructor for `HttpServer` on a Unix Domain Socket, using the provided
+    /// configuration.
+    ///
+    /// Returns the newly formed `HttpServer`.
+    ///
+    /// # Errors
+    /// Returns an `IOError` when binding or `epoll::create` fails.
+    pub fn new_uds_with_config<P: AsRef<Path>>(
+        path_to_socket: P,
+        config: HttpServerConfig,
+    ) -> Result<Self> {
         let socket = PollableListener::bind_uds(path_to_socket).map_err(ServerError::IOError)?;
+        Self::new(socket, config)
+    }
+
+    fn new(listener: PollableListener, config: HttpServerConfig) -> Result<Self> {
         let epoll = Epoll::new().map_err(ServerError::IOError)?;
+        let timer = TimerFd::new().map_err(ServerError::IOError)?;
+        let event_fd = EventFd::new().map_err(ServerError::IOError)?;
+        let (responses, response_receiver) = channel();
         Ok(Self {
-            socket,
+            listeners: vec![listener],
             epoll,
             connections: HashMap::new(),
+            connection_count: 0,
+            evicted_connections: Vec::new(),
+            config,
+            timer,
+            shutting_down: false,
+            shutdown_deadline: None,
+            access_log: None,
+            metrics: ServerMetrics::default(),
+            accept_paused: false,
+            started: false,
+            response_sender: ResponseSender {
+                responses,
+                event_fd: Arc::new(event_fd),
+            },
+            response_receiver,
+            workers: None,
         })
     }
 
+    /// Adds a TCP socket bound to `addr` on which the server listens for
+    /// connections, along with the ones it already listens on.
+    ///
+    /// # Errors
+    /// Returns an `IOError` when binding or adding the socket to `epoll` fails.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// extern crate micro_http;
+    /// use micro_http::HttpServer;
+    ///
+    /// let path_to_socket = "/tmp/example_listeners.sock";
+    /// std::fs::remove_file(path_to_socket).unwrap_or_default();
+    ///
+    /// let mut server = HttpServer::new_uds(path_to_socket).unwrap();
+    /// server.start_server().unwrap();
+    /// let tcp_listener = server.add_tcp_listener("127.0.0.1:0").unwrap();
+    /// assert_eq!(tcp_listener.index(), 1);
+    ///
+    /// // Connect a client to the server so it doesn't block in our example.
+    /// let _socket = std::os::unix::net::UnixStream::connect(path_to_socket).unwrap();
+    /// for request in server.requests().unwrap() {
+    ///     if request.listener() == tcp_listener {
+    ///         // Answer the requests received over TCP.
+    ///     }
+    /// }
+    /// ```
+    pub fn add_tcp_listener<A: ToSocketAddrs>(&mut self, addr: A) -> Result<ListenerId> {
+        let listener = PollableListener::bind_tcp(addr).map_err(ServerError::IOError)?;
+        self.add_listener(listener)
+    }
+
+    /// Adds a Unix domain socket bound to `path_to_socket` on which the server
+    /// listens for connections, along with the ones it already listens on.
+    ///
+    /// # Errors
+    /// Returns an `IOError` when binding or adding the socket to `epoll` fails.
+    pub fn add_uds_listener<P: AsRef<Path>>(&mut self, path_to_socket: P) -> Result<ListenerId> {
+        let listener = PollableListener::bind_uds(path_to_socket).map_err(ServerError::IOError)?;
+        self.add_listener(listener)
+    }
+
+    fn add_listener(&mut self, listener: PollableListener) -> Result<ListenerId> {
+        // The listener is added to the `epoll` structure along with the others,
+        // unless the server is not listening for connections at the moment.
+        if self.started && !self.accept_paused && !self.shutting_down {
+            Self::epoll_add(&self.epoll, listener.as_raw_fd())?;
+        }
+        self.listeners.push(listener);
+        Ok(ListenerId(self.listeners.len() - 1))
+    }
+
     /// Starts the HTTP Server.
     pub fn start_server(&mut self) -> Result<()> {
-        // Add the socket on which we listen for new connections to the
-        // `epoll` structure.
-        Self::epoll_add(&self.epoll, self.socket.as_raw_fd())
+        // Add the sockets on which we listen for new connections to the
+        // `epoll` structure, along with the timer which tells us when a
+        // connection times out and the event which tells us when responses
+        // were sent from other threads.
+        for listener in self.listeners.iter() {
+            Self::epoll_add(&self.epoll, listener.as_raw_fd())?;
+        }
+        Self::epoll_add(&self.epoll, self.timer.as_raw_fd())?;
+        Self::epoll_add(&self.epoll, self.response_sender.event_fd.as_raw_fd())?;
+        self.started = true;
+        Ok(())
     }
 
     pub fn requests(&mut self) -> Result<Vec<ServerRequest>> {
         let mut parsed_requests: Vec<ServerRequest> = vec![];
-        let mut events = vec![EpollEvent::default(); MAX_CONNECTIONS];
+        // There is nothing left to wait for.
+        if self.is_drained() {
+            return Ok(parsed_requests);
+        }
+        let max_events = self.config.max_events;
+        let mut events = vec![EpollEvent::default(); max_events];
         // This is a wrapper over the syscall `epoll_wait` and it will block the
         // current thread until at least one event is received.
         // The received notifications will then populate the `events` array with
-        // `event_count` elements, where 1 <= event_count <= MAX_CONNECTIONS.
-        let event_count = match self.epoll.wait(MAX_CONNECTIONS, &mut events[..]) {
+        // `event_count` elements, where 1 <= event_count <= max_events.
+        let event_count = match self.epoll.wait(max_events, &mut events[..]) {
             Ok(event_count) => event_count,
             Err(e) if e.raw_os_error() == Some(libc::EINTR) => 0,
             Err(e) => return Err(ServerError::IOError(e)),
         };
         // We use `take()` on the iterator over `events` as, even though only
         // `events_count` events have been inserted into `events`, the size of
-        // the array is still `MAX_CONNECTIONS`, so we discard empty elements
+        // the array is still `max_events`, so we discard empty elements
         // at the end of the array.
         for e in events.iter().take(event_count) {
             // Check the file descriptor which produced the notification `e`.
             // It could be that we have a new connection, or one of our open
             // connections is ready to exchange data with a client.
-            if e.fd() == self.socket.as_raw_fd() {
-                // We have received a notification on the listener socket, which
+            let listener_index = self
+                .listeners
+                .iter()
+                .position(|listener| listener.as_raw_fd() == e.fd());
+            if let Some(listener_index) = listener_index {
+                // We have received a notification on a listener socket, which
                 // means we have a new connection to accept.
-                match self.handle_new_connection() {
-                    // If the server is full, we send a message to the client
-                    // notifying them that we will close the connection, then
-                    // we discard it.
-                    Err(ServerError::ServerFull) => {
-                        self.socket
-                            .accept()
-                            .map_err(ServerError::IOError)
-                            .and_then(move |mut stream| {
-                                stream
-                                    .write(SERVER_FULL_ERROR_MESSAGE)
-                                    .map_err(ServerError::IOError)
-                            })?;
-                    }
+                match self.handle_new_connection(ListenerId(listener_index)) {
+                    // The client was already turned away.
+                    Err(ServerError::ServerFull) => self.metrics.rejected_connections += 1,
+                    // The client was already turned away.
+                    Err(ServerError::PeerNotAllowed) => self.metrics.forbidden_connections += 1,
                     // An internal error will compromise any in-flight requests.
                     Err(error) => return Err(error),
                     Ok(()) => {}
                 };
+            } else if e.fd() == self.timer.as_raw_fd() {
+                // The deadline of at least one connection has expired.
+                self.timer.clear().map_err(ServerError::IOError)?;
+                self.handle_timeouts()?;
+            } else if e.fd() == self.response_sender.event_fd.as_raw_fd() {
+                // Responses were sent from other threads, or the server was woken up.
+                self.response_sender
+                    .event_fd
+                    .clear()
+                    .map_err(ServerError::IOError)?;
+                let responses: Vec<ServerResponse> = self.response_receiver.try_iter().collect();
+                self.enqueue_responses(responses)?;
+                // The sources of the streaming bodies which had no data are read again.
+                for (fd, client_connection) in self.connections.iter() {
+                    if client_connection.state == ClientConnectionState::AwaitingOutgoing
+                        && client_connection.connection.body_stream_blocked()
+                    {
+                        Self::epoll_mod(&self.epoll, *fd, EventSet::new(EPOLL_OUT))?;
+                    }
+                }
             } else {
                 // We have a notification on one of our open connections.
                 let fd = e.fd();
-                let client_connection = self.connections.get_mut(&fd).unwrap();
+                let client_connection = match self.connections.get_mut(&fd) {
+                    Some(client_connection) => client_connection,
+                    // The connection was dropped while handling an earlier event.
+                    None => continue,
+                };
                 if e.event_set().contains(EPOLL_IN) {
                     // We have bytes to read from this connection.
-                    // If our `read` yields `Request` objects, we wrap them with an ID before
-                    // handing them to the user.
-                    parsed_requests.append(
-                        &mut client_connection
-                            .read()?
-                            .into_iter()
-                            .map(|request| ServerRequest::new(request, e.data()))
-                            .collect(),
-                    );
+                    // Our `read` yields `Request` objects wrapped with an ID, which we
+                    // hand to the user.
+                    parsed_requests
+                        .append(&mut client_connection.read(&self.config, &mut self.metrics)?);
+                    client_connection.update_phase(Instant::now());
                     // If the connection was incoming before we read and we now have to write
                     // either an error message or an `expect` response, we change its `epoll`
                     // event set to notify us when the stream is ready for writing.
@@ -353,88 +1107,466 @@ impl HttpServer {
                 } else if e.event_set().contains(EPOLL_OUT) {
                     // We have bytes to write on this connection.
                     client_connection.write()?;
+                    client_connection.update_phase(Instant::now());
+                    for entry in client_connection.sent_responses() {
+                        self.metrics.record_response(&entry);
+                        if let Some(access_log) = self.access_log.as_mut() {
+                            access_log.log(&entry);
+                        }
+                    }
                     // If the connection was outgoing before we tried to write the responses
                     // and we don't have any more responses to write, we change the `epoll`
                     // event set to notify us when we have bytes to read from the stream.
                     if client_connection.state == ClientConnectionState::AwaitingIncoming {
                         Self::epoll_mod(&self.epoll, fd, EventSet::new(EPOLL_IN))?;
+                    } else if client_connection.connection.body_stream_blocked() {
+                        // The stream stays writable, so waiting for it would spin until
+                        // the source of the body has data. We only listen for the client
+                        // closing the connection until the server is woken up.
+                        Self::epoll_mod(&self.epoll, fd, EventSet::new(EPOLL_IN))?;
+                    } else if client_connection.state == ClientConnectionState::Closed {
+                        // We will not exchange any more data on this connection, so we
+                        // stop listening for its notifications until it is dropped.
+                        Self::epoll_del(&self.epoll, fd)?;
                     }
                 }
+                let (bytes_read, bytes_written) = client_connection.connection.take_transferred();
+                self.metrics.bytes_read += bytes_read as u64;
+                self.metrics.bytes_written += bytes_written as u64;
             }
         }
 
+        if self.shutting_down {
+            self.close_drained_connections()?;
+        }
+
         // Remove dead connections.
         self.connections
             .retain(|_, client_connection| !client_connection.is_done());
+        self.evicted_connections.clear();
+
+        if self.accept_paused
+            && !self.shutting_down
+            && self.connections.len() < self.config.max_connections
+        {
+            // A slot was freed, so we listen for new connections again.
+            for listener in self.listeners.iter() {
+                Self::epoll_add(&self.epoll, listener.as_raw_fd())?;
+            }
+            self.accept_paused = false;
+        }
 
+        self.arm_timer()?;
+        if let Some(workers) = self.workers.as_ref() {
+            for request in parsed_requests.drain(..) {
+                workers.dispatch(request);
+            }
+        }
         Ok(parsed_requests)
     }
 
-    pub fn epoll(&self) -> &Epoll {
-        &self.epoll
+    /// Returns a handle through which other threads can hand responses to the
+    /// server or wake it up.
+    pub fn response_sender(&self) -> ResponseSender {
+        self.response_sender.clone()
     }
 
-    /// Enqueues the provided responses in the outgoing connection.
+    /// Hands the requests received from now on to `worker_count` threads, which
+    /// answer them with `handler`, so that a slow handler does not hold up the
+    /// other connections. `requests` then hands out no requests, and the
+    /// responses are sent as soon as the workers produce them. Workers started
+    /// earlier answer the requests handed to them and stop before this returns.
+    ///
+    /// A request whose handler panics is answered with
+    /// `500 Internal Server Error`.
     ///
     /// # Errors
-    /// `IOError` is returned when an `epoll::ctl` operation fails.
-    pub fn enqueue_responses(&mut self, responses: Vec<ServerResponse>) -> Result<()> {
-        for response in responses {
-            self.respond(response)?;
+    /// `IOError` is returned when a thread can not be spawned or `worker_count` is
+    /// zero, in which case the server hands out the requests from `requests` again.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// extern crate micro_http;
+    /// use micro_http::{HttpServer, Message, Response, StatusCode};
+    ///
+    /// let path_to_socket = "/tmp/example_workers.sock";
+    /// std::fs::remove_file(path_to_socket).unwrap_or_default();
+    ///
+    /// let mut server = HttpServer::new_uds(path_to_socket).unwrap();
+    /// server.start_server().unwrap();
+    /// server
+    ///     .start_workers(4, |request| {
+    ///         // Your code here.
+    ///         Response::new(request.inner().version(), StatusCode::NoContent)
+    ///     })
+    ///     .unwrap();
+    ///
+    /// // Connect a client to the server so it doesn't block in our example.
+    /// let _socket = std::os::unix::net::UnixStream::connect(path_to_socket).unwrap();
+    ///
+    /// // The responses are sent while the server waits for requests.
+    /// assert!(server.requests().unwrap().is_empty());
+    /// ```
+    pub fn start_workers<F>(&mut self, worker_count: usize, handler: F) -> Result<()>
+    where
+        F: Fn(&ServerRequest) -> Response + Send + Sync + 'static,
+    {
+        let handler: Arc<WorkerHandler> = Arc::new(handler);
+        self.workers = None;
+        self.workers = Some(
+            WorkerPool::new(
+                worker_count,
+                handler,
+                self.response_sender.clone(),
+                &self.config,
+            )
+            .map_err(ServerError::IOError)?,
+        );
+        Ok(())
+    }
+
+    /// Sets the log to which a line is written for every response sent, or
+    /// disables logging if `access_log` is `None`.
+    pub fn set_access_log(&mut self, access_log: Option<AccessLog>) {
+        self.access_log = access_log;
+    }
+
+    /// Returns a snapshot of the counters and gauges kept by the server.
+    pub fn metrics(&self) -> ServerMetrics {
+        let mut metrics = self.metrics.clone();
+        metrics.open_connections = self
+            .connections
+            .values()
+            .filter(|client_connection| client_connection.state != ClientConnectionState::Closed)
+            .count() as u64;
+        metrics
+    }
+
+    /// Starts shutting the server down.
+    ///
+    /// The server stops accepting connections and parsing requests, while the
+    /// requests handed out so far can still be answered through `respond`. Each
+    /// connection is closed once its last response is sent, or when `timeout`
+    /// expires, whichever comes first. Calls to `requests` are then needed until
+    /// `is_drained` returns `true`.
+    ///
+    /// # Errors
+    /// `IOError` is returned when an `epoll::ctl` operation fails or the timer can
+    /// not be set.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// extern crate micro_http;
+    /// use micro_http::HttpServer;
+    /// use std::time::Duration;
+    ///
+    /// let path_to_socket = "/tmp/example_shutdown.sock";
+    /// std::fs::remove_file(path_to_socket).unwrap_or_default();
+    ///
+    /// let mut server = HttpServer::new_uds(path_to_socket).unwrap();
+    /// server.start_server().unwrap();
+    ///
+    /// server.shutdown(Some(Duration::from_secs(5))).unwrap();
+    /// while !server.is_drained() {
+    ///     for _request in server.requests().unwrap() {
+    ///         // Answer `request`.
+    ///     }
+    /// }
+    /// ```
+    pub fn shutdown(&mut self, timeout: Option<Duration>) -> Result<()> {
+        if self.shutting_down {
+            return Ok(());
+        }
+        self.shutting_down = true;
+        self.shutdown_deadline = timeout.map(|timeout| Instant::now() + timeout);
+        if !self.accept_paused {
+            for listener in self.listeners.iter() {
+                Self::epoll_del(&self.epoll, listener.as_raw_fd())?;
+            }
         }
 
-        Ok(())
+        for (fd, client_connection) in self.connections.iter_mut() {
+            if client_connection.state == ClientConnectionState::Closed {
+                continue;
+            }
+            client_connection.shut_down();
+            if client_connection.state == ClientConnectionState::Closed {
+                Self::epoll_del(&self.epoll, *fd)?;
+            }
+        }
+        self.connections
+            .retain(|_, client_connection| !client_connection.is_done());
+
+        self.arm_timer()
     }
 
-    /// Adds the provided response to the outgoing buffer in the corresponding connection.
+    /// Returns `true` if the server was shut down and all of its connections are
+    /// closed.
+    pub fn is_drained(&self) -> bool {
+        self.shutting_down && self.connections.is_empty()
+    }
+
+    /// Closes the connections which have no responses left to send during shutdown.
     ///
     /// # Errors
     /// `IOError` is returned when an `epoll::ctl` operation fails.
-    pub fn respond(&mut self, response: ServerResponse) -> Result<()> {
-        if let Some(client_connection) = self.connections.get_mut(&(response.id as i32)) {
-            // If the connection was incoming before we enqueue the response, we change its
-            // `epoll` event set to notify us when the stream is ready for writing.
-            if let ClientConnectionState::AwaitingIncoming = client_connection.state {
-                client_connection.state = ClientConnectionState::AwaitingOutgoing;
-                Self::epoll_mod(&self.epoll, response.id as RawFd, EventSet::new(EPOLL_OUT))?;
+    fn close_drained_connections(&mut self) -> Result<()> {
+        for (fd, client_connection) in self.connections.iter_mut() {
+            if client_connection.state == ClientConnectionState::AwaitingIncoming
+                && client_connection.in_flight_response_count == 0
+            {
+                client_connection.state = ClientConnectionState::Closed;
+                Self::epoll_del(&self.epoll, *fd)?;
             }
-            client_connection.enqueue_response(response.response);
         }
         Ok(())
     }
 
-    /// Accepts a new incoming connection and adds it to the `epoll` notification structure.
+    /// Times out the connections whose deadline has expired.
     ///
     /// # Errors
-    /// `IOError` is returned when socket or epoll operations fail.
-    /// `ServerFull` is returned if server full capacity has been reached.
-    fn handle_new_connection(&mut self) -> Result<()> {
-        if self.connections.len() == MAX_CONNECTIONS {
-            // If we want a replacement policy for connections
-            // this is where we will have it.
-            return Err(ServerError::ServerFull);
+    /// `IOError` is returned when an `epoll::ctl` operation fails.
+    fn handle_timeouts(&mut self) -> Result<()> {
+        let now = Instant::now();
+        if self
+            .shutdown_deadline
+            .is_some_and(|deadline| deadline <= now)
+        {
+            // The connections still open are dropped along with their responses.
+            for (fd, client_connection) in self.connections.drain() {
+                if client_connection.state != ClientConnectionState::Closed {
+                    Self::epoll_del(&self.epoll, fd)?;
+                }
+            }
+            return Ok(());
         }
-
-        self.socket
-            .accept()
-            .map_err(ServerError::IOError)
-            .and_then(|stream| {
-                // `HttpConnection` is supposed to work with non-blocking streams.
-                stream
-                    .set_nonblocking(true)
-                    .map(|_| stream)
-                    .map_err(ServerError::IOError)
-            })
-            .and_then(|stream| {
-                // Add the stream to the `epoll` structure and listen for bytes to be read.
-                Self::epoll_add(&self.epoll, stream.as_raw_fd())?;
-                // Then add it to our open connections.
-                self.connections.insert(
-                    stream.as_raw_fd(),
-                    ClientConnection::new(HttpConnection::new(stream)),
-                );
+        for (fd, client_connection) in self.connections.iter_mut() {
+            match client_connection.deadline(&self.config) {
+                Some(deadline) if deadline <= now => {}
+                _ => continue,
+            }
+            client_connection.time_out(&self.config);
+            match client_connection.state {
+                ClientConnectionState::AwaitingOutgoing => {
+                    Self::epoll_mod(&self.epoll, *fd, EventSet::new(EPOLL_OUT))?
+                }
+                ClientConnectionState::Closed => Self::epoll_del(&self.epoll, *fd)?,
+                ClientConnectionState::AwaitingIncoming => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Arms the timer to expire at the earliest deadline of the connections, or
+    /// disarms it if none of them can time out.
+    ///
+    /// # Errors
+    /// `IOError` is returned when the timer can not be set.
+    fn arm_timer(&self) -> Result<()> {
+        let deadline = self
+            .connections
+            .values()
+            .filter_map(|client_connection| client_connection.deadline(&self.config))
+            .chain(self.shutdown_deadline)
+            .min();
+        match deadline {
+            Some(deadline) => {
+                // A zero timeout would disarm the timer instead of firing it right away.
+                let timeout = std::cmp::max(
+                    deadline.saturating_duration_since(Instant::now()),
+                    Duration::from_nanos(1),
+                );
+                self.timer.arm(timeout)
+            }
+            None => self.timer.disarm(),
+        }
+        .map_err(ServerError::IOError)
+    }
+
+    pub fn epoll(&self) -> &Epoll {
+        &self.epoll
+    }
+
+    /// Enqueues the provided responses in the outgoing connection.
+    /// Responses to requests whose connection no longer exists or which were
+    /// already answered are dropped.
+    ///
+    /// # Errors
+    /// `IOError` is returned when an `epoll::ctl` operation fails.
+    pub fn enqueue_responses(&mut self, responses: Vec<ServerResponse>) -> Result<()> {
+        for response in responses {
+            match self.respond(response) {
+                // There is no one left to receive the response, or the client
+                // already got one, so it is dropped.
+                Ok(()) | Err(ServerError::ConnectionGone) | Err(ServerError::AlreadyAnswered) => {}
+                Err(error) => return Err(error),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Adds the provided response to the outgoing buffer in the corresponding connection.
+    ///
+    /// Responses are sent in the order in which their requests were received, so a
+    /// response is held back until the requests received before its own are answered.
+    ///
+    /// # Errors
+    /// `IOError` is returned when an `epoll::ctl` operation fails.
+    /// `ConnectionGone` is returned when the connection on which the request was
+    /// received no longer exists.
+    /// `AlreadyAnswered` is returned when a response to the request was already
+    /// given, in which case `response` is dropped.
+    pub fn respond(&mut self, response: ServerResponse) -> Result<()> {
+        let id = response.id;
+        match self.connections.get_mut(&id.fd) {
+            Some(client_connection) if client_connection.generation == id.generation => {
+                client_connection.enqueue_response(id.sequence, response.response)?;
+                // If the connection was incoming before we enqueue the response and the
+                // response is not held back, we change its `epoll` event set to notify us
+                // when the stream is ready for writing.
+                if client_connection.state == ClientConnectionState::AwaitingIncoming
+                    && client_connection.connection.pending_write()
+                {
+                    client_connection.state = ClientConnectionState::AwaitingOutgoing;
+                    Self::epoll_mod(&self.epoll, id.fd, EventSet::new(EPOLL_OUT))?;
+                }
                 Ok(())
-            })
+            }
+            _ => Err(ServerError::ConnectionGone),
+        }
+    }
+
+    /// Accepts a new incoming connection on `listener` and adds it to the `epoll`
+    /// notification structure.
+    ///
+    /// When the server is full, the overload policy decides whether a connection
+    /// is evicted to make room, the client is rejected or the listening sockets
+    /// are left out of the `epoll` structure until a connection is closed.
+    ///
+    /// # Errors
+    /// `IOError` is returned when socket or epoll operations fail.
+    /// `ServerFull` is returned if the client was rejected because the server is full.
+    /// `PeerNotAllowed` is returned if the client is not allowed to connect.
+    fn handle_new_connection(&mut self, listener: ListenerId) -> Result<()> {
+        // Another listener may have paused accepting earlier in the same batch
+        // of events, in which case the connection waits until there is room.
+        if self.accept_paused {
+            return Ok(());
+        }
+        if self.connections.len() >= self.config.max_connections {
+            match self.config.overload_policy {
+                OverloadPolicy::PauseAccept => {
+                    for listener in self.listeners.iter() {
+                        Self::epoll_del(&self.epoll, listener.as_raw_fd())?;
+                    }
+                    self.accept_paused = true;
+                    return Ok(());
+                }
+                OverloadPolicy::EvictIdle
+                | OverloadPolicy::Reject
+                | OverloadPolicy::Reserve { .. } => {}
+            }
+        }
+
+        let (mut stream, peer) = match self.listeners[listener.0]
+            .accept()
+            .map_err(ServerError::IOError)?
+        {
+            Some(connection) => connection,
+            // The connection was dropped, as its peer could not be identified.
+            // The other connections are not affected by it.
+            None => return Ok(()),
+        };
+        if let Some(allowed_peers) = self.config.allowed_peers.as_ref() {
+            if !allowed_peers.allows(&peer) {
+                let mut forbidden_response = self.config.error_response(
+                    StatusCode::Forbidden,
+                    "The client is not allowed to connect.",
+                );
+                forbidden_response.with_connection_close();
+                // The connection is dropped right away, so failing to tell the
+                // client why does not concern the server. The stream does not
+                // block, so a client which does not read can not hold it up.
+                if stream.set_nonblocking(true).is_ok() {
+                    let _ = forbidden_response.send(&mut stream);
+                }
+                return Err(ServerError::PeerNotAllowed);
+            }
+        }
+
+        let max_connections = match self.config.overload_policy {
+            OverloadPolicy::Reserve {
+                connections,
+                ref peers,
+            } if !peers.allows(&peer) => self.config.max_connections.saturating_sub(connections),
+            _ => self.config.max_connections,
+        };
+        // Only a client which is going to be admitted takes the place of an idle
+        // connection.
+        if self.connections.len() >= max_connections
+            && self.config.overload_policy == OverloadPolicy::EvictIdle
+        {
+            self.evict_idle_connection()?;
+        }
+        if self.connections.len() >= max_connections {
+            let mut server_full_response = self
+                .config
+                .error_response(StatusCode::ServiceUnavailable, "Too many open connections");
+            server_full_response.with_connection_close();
+            if let Some(retry_after) = self.config.retry_after {
+                let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+                server_full_response.with_header("Retry-After".to_string(), seconds.to_string());
+            }
+            // The response is only written as far as the stream takes it without
+            // blocking, so a client which does not read can not hold up the server.
+            // Failing to tell the client why does not concern the server.
+            if stream.set_nonblocking(true).is_ok() {
+                let _ = server_full_response.send(&mut stream);
+            }
+            return Err(ServerError::ServerFull);
+        }
+
+        // `HttpConnection` is supposed to work with non-blocking streams.
+        stream.set_nonblocking(true).map_err(ServerError::IOError)?;
+        // Add the stream to the `epoll` structure and listen for bytes to be read.
+        let stream_fd = stream.as_raw_fd();
+        Self::epoll_add(&self.epoll, stream_fd)?;
+        // Then add it to our open connections.
+        let mut connection = HttpConnection::new(stream);
+        connection.set_limits(self.config.limits);
+        connection.set_buffer_size(self.config.buffer_size);
+        self.connections.insert(
+            stream_fd,
+            ClientConnection::new(connection, stream_fd, self.connection_count, peer, listener),
+        );
+        self.connection_count += 1;
+        self.metrics.accepted_connections += 1;
+        Ok(())
+    }
+
+    /// Closes the idle connection on which data was exchanged the longest time
+    /// ago, if there is one, once the current batch of events is handled.
+    ///
+    /// # Errors
+    /// `IOError` is returned when an `epoll::ctl` operation fails.
+    fn evict_idle_connection(&mut self) -> Result<()> {
+        let evicted_fd = self
+            .connections
+            .iter()
+            .filter(|(_, client_connection)| client_connection.is_idle())
+            .min_by_key(|(_, client_connection)| client_connection.last_activity)
+            .map(|(fd, _)| *fd);
+        if let Some(fd) = evicted_fd {
+            Self::epoll_del(&self.epoll, fd)?;
+            if let Some(client_connection) = self.connections.remove(&fd) {
+                self.evicted_connections.push(client_connection);
+            }
+            self.metrics.evicted_connections += 1;
+        }
+        Ok(())
     }
 
     /// Changes the event type for a connection to either listen for incoming bytes
@@ -449,6 +1581,16 @@ impl HttpServer {
             .map_err(ServerError::IOError)
     }
 
+    /// Removes a stream from the `epoll` notification structure.
+    ///
+    /// # Errors
+    /// `IOError` is returned when an `EPOLL_CTL_DEL` control operation fails.
+    fn epoll_del(epoll: &Epoll, stream_fd: RawFd) -> Result<()> {
+        epoll
+            .ctl(ControlOperation::Delete, stream_fd, &EpollEvent::default())
+            .map_err(ServerError::IOError)
+    }
+
     /// Adds a stream to the `epoll` notification structure with the `EPOLLIN` event set.
     ///
     /// # Errors
@@ -469,13 +1611,40 @@ mod tests {
     extern crate vmm_sys_util;
 
     use super::*;
+    use access_log::AccessLogFormat;
+    use common::net::PeerCredentials;
+    use router::Router;
     use std::io::{Read, Write};
     use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream};
     use std::os::unix::net::UnixStream;
 
     use server::tests::vmm_sys_util::tempfile::TempFile;
 
-    const local_addr: IpAddr = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
+    const LOCAL_ADDR: IpAddr = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
+
+    // A sink which can be read while the server owns a handle to it.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedBuffer {
+        fn lines(&self) -> Vec<String> {
+            String::from_utf8(self.0.lock().unwrap().clone())
+                .unwrap()
+                .lines()
+                .map(str::to_string)
+                .collect()
+        }
+    }
 
     fn get_temp_socket_file() -> TempFile {
         let mut path_to_socket = TempFile::new().unwrap();
@@ -484,22 +1653,18 @@ mod tests {
     }
 
     fn get_temp_tcp_server() -> Result<(HttpServer, u16)> {
-        let mut port = 36000;
-        while port <= std::u16::MAX {
-            if let Ok(server) = HttpServer::new_tcp(SocketAddr::new(local_addr, port)) {
+        for port in 36000..=u16::MAX {
+            if let Ok(server) = HttpServer::new_tcp(SocketAddr::new(LOCAL_ADDR, port)) {
                 return Ok((server, port));
             }
-            port += 1;
-        };
+        }
         Err(ServerError::ServerFull)
     }
 
     impl PartialEq for Request {
         fn eq(&self, other: &Self) -> bool {
             // Ignore the other fields of Request for now because they are not used.
-            self.request_line == other.request_line
-                && self.headers.content_length() == other.headers.content_length()
-                && self.headers.map == other.headers.map
+            self.request_line == other.request_line && self.headers == other.headers
         }
     }
 
@@ -546,7 +1711,7 @@ mod tests {
         server.start_server().unwrap();
 
         // Test one incoming connection.
-        let mut socket = TcpStream::connect(SocketAddr::new(local_addr, port)).unwrap();
+        let mut socket = TcpStream::connect(SocketAddr::new(LOCAL_ADDR, port)).unwrap();
         assert!(server.requests().unwrap().is_empty());
 
         socket
@@ -653,7 +1818,7 @@ mod tests {
         server.start_server().unwrap();
 
         // Test two concurrent connections.
-        let mut first_socket = TcpStream::connect(SocketAddr::new(local_addr, port)).unwrap();
+        let mut first_socket = TcpStream::connect(SocketAddr::new(LOCAL_ADDR, port)).unwrap();
         assert!(server.requests().unwrap().is_empty());
 
         first_socket
@@ -663,7 +1828,7 @@ mod tests {
                                Content-Type: application/json\r\n\r\nwhatever body",
             )
             .unwrap();
-        let mut second_socket = TcpStream::connect(SocketAddr::new(local_addr, port)).unwrap();
+        let mut second_socket = TcpStream::connect(SocketAddr::new(LOCAL_ADDR, port)).unwrap();
 
         let mut req_vec = server.requests().unwrap();
         let server_request = req_vec.remove(0);
@@ -772,7 +1937,7 @@ mod tests {
         server.start_server().unwrap();
 
         // Test one incoming connection with `Expect: 100-continue`.
-        let mut socket = TcpStream::connect(SocketAddr::new(local_addr, port)).unwrap();
+        let mut socket = TcpStream::connect(SocketAddr::new(LOCAL_ADDR, port)).unwrap();
         assert!(server.requests().unwrap().is_empty());
 
         socket
@@ -829,9 +1994,14 @@ mod tests {
 
         sockets.push(UnixStream::connect(path_to_socket.as_path()).unwrap());
         assert!(server.requests().unwrap().is_empty());
-        let mut buf: [u8; 120] = [0; 120];
+        let server_full_message = b"HTTP/1.1 503 Service Unavailable\r\n\
+                                    Server: Firecracker API\r\n\
+                                    Connection: close\r\n\
+                                    Content-Length: 40\r\n\r\n\
+                                    { \"error\": \"Too many open connections\" }";
+        let mut buf = vec![0u8; server_full_message.len()];
         sockets[MAX_CONNECTIONS].read_exact(&mut buf).unwrap();
-        assert_eq!(&buf[..], SERVER_FULL_ERROR_MESSAGE);
+        assert_eq!(&buf[..], &server_full_message[..]);
 
         // TCP
         let (mut server, port) = get_temp_tcp_server().unwrap();
@@ -839,15 +2009,19 @@ mod tests {
 
         let mut sockets: Vec<TcpStream> = Vec::with_capacity(11);
         for _ in 0..MAX_CONNECTIONS {
-            sockets.push(TcpStream::connect(SocketAddr::new(local_addr, port)).unwrap());
+            sockets.push(TcpStream::connect(SocketAddr::new(LOCAL_ADDR, port)).unwrap());
             assert!(server.requests().unwrap().is_empty());
         }
 
-        sockets.push(TcpStream::connect(SocketAddr::new(local_addr, port)).unwrap());
+        sockets.push(TcpStream::connect(SocketAddr::new(LOCAL_ADDR, port)).unwrap());
         assert!(server.requests().unwrap().is_empty());
-        let mut buf: [u8; 120] = [0; 120];
-        sockets[MAX_CONNECTIONS].read_exact(&mut buf).unwrap();
-        assert_eq!(&buf[..], SERVER_FULL_ERROR_MESSAGE);
+        // The reply can be parsed by our own client.
+        let mut response = Response::receive(&mut sockets[MAX_CONNECTIONS]).unwrap();
+        assert_eq!(response.status(), StatusCode::ServiceUnavailable);
+        assert_eq!(
+            response.body().unwrap().as_slice(),
+            &b"{ \"error\": \"Too many open connections\" }"[..]
+        );
     }
 
     #[test]
@@ -873,9 +2047,11 @@ mod tests {
 
         assert!(server.requests().unwrap().is_empty());
         assert!(server.requests().unwrap().is_empty());
-        let mut buf: [u8; 116] = [0; 116];
+        let mut buf: [u8; 172] = [0; 172];
         assert!(socket.read(&mut buf[..]).unwrap() > 0);
-        let error_message = b"HTTP/1.1 400\r\n\
+        let error_message = b"HTTP/1.1 400 Bad Request\r\n\
+                              Server: Firecracker API\r\n\
+                              Connection: close\r\n\
                               Content-Length: 80\r\n\r\n{ \"error\": \"Invalid header.\n\
                               All previous unanswered requests will be dropped.\" }";
         assert_eq!(&buf[..], &error_message[..]);
@@ -885,7 +2061,7 @@ mod tests {
         server.start_server().unwrap();
 
         // Test one incoming connection.
-        let mut socket = TcpStream::connect(SocketAddr::new(local_addr, port)).unwrap();
+        let mut socket = TcpStream::connect(SocketAddr::new(LOCAL_ADDR, port)).unwrap();
         socket.set_nonblocking(true).unwrap();
         assert!(server.requests().unwrap().is_empty());
 
@@ -899,9 +2075,11 @@ mod tests {
 
         assert!(server.requests().unwrap().is_empty());
         assert!(server.requests().unwrap().is_empty());
-        let mut buf: [u8; 116] = [0; 116];
+        let mut buf: [u8; 172] = [0; 172];
         assert!(socket.read(&mut buf[..]).unwrap() > 0);
-        let error_message = b"HTTP/1.1 400\r\n\
+        let error_message = b"HTTP/1.1 400 Bad Request\r\n\
+                              Server: Firecracker API\r\n\
+                              Connection: close\r\n\
                               Content-Length: 80\r\n\r\n{ \"error\": \"Invalid header.\n\
                               All previous unanswered requests will be dropped.\" }";
         assert_eq!(&buf[..], &error_message[..]);
@@ -995,7 +2173,7 @@ mod tests {
         // Test a connection dropped and then a new one appearing
         // before the user had a chance to send the response to the
         // first one.
-        let mut first_socket = TcpStream::connect(SocketAddr::new(local_addr, port)).unwrap();
+        let mut first_socket = TcpStream::connect(SocketAddr::new(LOCAL_ADDR, port)).unwrap();
         assert!(server.requests().unwrap().is_empty());
 
         first_socket
@@ -1011,7 +2189,7 @@ mod tests {
 
         first_socket.shutdown(std::net::Shutdown::Both).unwrap();
         assert!(server.requests().unwrap().is_empty());
-        let mut second_socket = TcpStream::connect(SocketAddr::new(local_addr, port)).unwrap();
+        let mut second_socket = TcpStream::connect(SocketAddr::new(LOCAL_ADDR, port)).unwrap();
         second_socket.set_nonblocking(true).unwrap();
         assert!(server.requests().unwrap().is_empty());
 
@@ -1064,4 +2242,1685 @@ mod tests {
         second_socket.shutdown(std::net::Shutdown::Both).unwrap();
         assert!(server.requests().is_ok());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_wait_chunked_request() {
+        let path_to_socket = get_temp_socket_file();
+
+        let mut server = HttpServer::new_uds(path_to_socket.as_path()).unwrap();
+        server.start_server().unwrap();
+
+        let mut socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+
+        // Split the chunks across multiple reads, both inside the chunk data
+        // and inside a chunk size line.
+        socket
+            .write_all(
+                b"PUT /machine-config HTTP/1.1\r\n\
+                         Transfer-Encoding: chunked\r\n\
+                         Content-Type: application/json\r\n\r\n\
+                         8\r\nwhat",
+            )
+            .unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        socket.write_all(b"ever\r\n5;ext=1\r\n body\r\n").unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        socket
+            .write_all(b"0\r\nChecksum: abc\r\nContent-Length: 3\r\n\r\n")
+            .unwrap();
+
+        let mut req_vec = server.requests().unwrap();
+        assert_eq!(req_vec.len(), 1);
+        let server_request = req_vec.remove(0);
+        let response = server_request
+            .process(|_request| Response::new(Version::Http11, StatusCode::NoContent));
+        let mut request = server_request.request;
+        assert!(request.headers.chunked());
+        assert_eq!(request.body().unwrap().as_slice(), b"whatever body");
+        assert_eq!(
+            request.trailers().header_line("Checksum"),
+            Some(&"abc".to_string())
+        );
+        // Trailer fields are only stored, as the body is already framed.
+        assert_eq!(
+            request.trailers().header_line("Content-Length"),
+            Some(&"3".to_string())
+        );
+        assert_eq!(request.trailers().content_length(), 0);
+
+        // A chunk that is not followed by CR LF invalidates the request.
+        socket
+            .write_all(
+                b"PUT /machine-config HTTP/1.1\r\n\
+                         Transfer-Encoding: chunked\r\n\r\n\
+                         4\r\nwhatever\r\n0\r\n\r\n",
+            )
+            .unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        // The error response is sent after the response to the previous request.
+        server.respond(response).unwrap();
+        while server
+            .connections
+            .values()
+            .any(|client_connection| client_connection.connection.pending_write())
+        {
+            assert!(server.requests().unwrap().is_empty());
+        }
+        let mut buf: [u8; 1024] = [0; 1024];
+        let bytes_read = socket.read(&mut buf[..]).unwrap();
+        assert!(buf[..bytes_read].starts_with(b"HTTP/1.1 204 No Content\r\n\r\n"));
+        assert!(String::from_utf8_lossy(&buf[..bytes_read])
+            .contains("\r\n\r\nHTTP/1.1 400 Bad Request\r\n"));
+
+        // A body delimited both by its length and by chunks is ambiguous.
+        let mut socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        socket
+            .write_all(
+                b"PUT /machine-config HTTP/1.1\r\n\
+                         Content-Length: 13\r\n\
+                         Transfer-Encoding: chunked\r\n\r\n\
+                         8\r\nwhatever\r\n0\r\n\r\n",
+            )
+            .unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        assert!(server.requests().unwrap().is_empty());
+        let bytes_read = socket.read(&mut buf[..]).unwrap();
+        assert!(buf[..bytes_read].starts_with(b"HTTP/1.1 400 Bad Request\r\n"));
+    }
+
+    #[test]
+    fn test_wait_streaming_response() {
+        let path_to_socket = get_temp_socket_file();
+
+        let mut server = HttpServer::new_uds(path_to_socket.as_path()).unwrap();
+        server.start_server().unwrap();
+
+        // HTTP/1.1 responses are sent using the chunked transfer coding.
+        let mut socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        socket.write_all(b"GET /logs HTTP/1.1\r\n\r\n").unwrap();
+
+        let mut req_vec = server.requests().unwrap();
+        let server_request = req_vec.remove(0);
+        let body = vec![b'a'; 5000];
+        let stream_body = body.clone();
+        server
+            .respond(server_request.process(move |_request| {
+                let mut response = Response::new(Version::Http11, StatusCode::OK);
+                response.with_body_stream(std::io::Cursor::new(stream_body.clone()));
+                response
+            }))
+            .unwrap();
+        while server
+            .connections
+            .values()
+            .any(|client_connection| client_connection.connection.pending_write())
+        {
+            assert!(server.requests().unwrap().is_empty());
+        }
+
+        let mut expected_response =
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n1000\r\n".to_vec();
+        expected_response.extend_from_slice(&body[..4096]);
+        expected_response.extend_from_slice(b"\r\n388\r\n");
+        expected_response.extend_from_slice(&body[4096..]);
+        expected_response.extend_from_slice(b"\r\n0\r\n\r\n");
+        let mut buf = vec![0u8; expected_response.len()];
+        socket.read_exact(&mut buf[..]).unwrap();
+        assert_eq!(buf, expected_response);
+        socket.set_nonblocking(true).unwrap();
+        assert!(socket.read(&mut buf[..]).is_err());
+        assert_eq!(server.connections.len(), 1);
+
+        // HTTP/1.0 responses end when the connection is closed.
+        let mut socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        socket.write_all(b"GET /logs HTTP/1.0\r\n\r\n").unwrap();
+
+        let mut req_vec = server.requests().unwrap();
+        let server_request = req_vec.remove(0);
+        server
+            .respond(server_request.process(|_request| {
+                let mut response = Response::new(Version::Http10, StatusCode::OK);
+                response.with_body_stream(std::io::Cursor::new(b"streamed body".to_vec()));
+                response
+            }))
+            .unwrap();
+        while server.connections.len() == 2 {
+            assert!(server.requests().unwrap().is_empty());
+        }
+
+        let mut buf = vec![];
+        socket.read_to_end(&mut buf).unwrap();
+        assert_eq!(
+            &buf[..],
+            &b"HTTP/1.0 200 OK\r\nConnection: close\r\n\r\nstreamed body"[..]
+        );
+    }
+
+    #[test]
+    fn test_wait_head() {
+        let path_to_socket = get_temp_socket_file();
+
+        let mut server = HttpServer::new_uds(path_to_socket.as_path()).unwrap();
+        server.start_server().unwrap();
+
+        let mut socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+
+        // The body of a response to a HEAD request is omitted, but its length is kept.
+        socket
+            .write_all(b"HEAD /machine-config HTTP/1.1\r\n\r\n")
+            .unwrap();
+        let mut req_vec = server.requests().unwrap();
+        let server_request = req_vec.remove(0);
+        assert_eq!(server_request.request.method(), Method::Head);
+        server
+            .respond(server_request.process(|_request| {
+                let mut response = Response::new(Version::Http11, StatusCode::OK);
+                response.with_body(b"response body");
+                response
+            }))
+            .unwrap();
+        assert!(server.requests().unwrap().is_empty());
+
+        socket.set_nonblocking(true).unwrap();
+        let mut buf: [u8; 1024] = [0; 1024];
+        let bytes_read = socket.read(&mut buf[..]).unwrap();
+        assert_eq!(
+            &buf[..bytes_read],
+            &b"HTTP/1.1 200 OK\r\nContent-Length: 13\r\n\r\n"[..]
+        );
+    }
+
+    #[test]
+    fn test_wait_request_limits() {
+        let path_to_socket = get_temp_socket_file();
+
+        let mut config = HttpServerConfig::default();
+        config.with_request_limits(RequestLimits {
+            max_request_line_len: 64,
+            max_header_count: 2,
+            max_headers_len: 128,
+            max_body_len: 16,
+        });
+        let mut server = HttpServer::new_uds_with_config(path_to_socket.as_path(), config).unwrap();
+        server.start_server().unwrap();
+
+        let requests: [&[u8]; 6] = [
+            b"GET /0123456789012345678901234567890123456789012345678901234567890123 HTTP/1.1\r\n\r\n",
+            b"GET / HTTP/1.1\r\nA: a\r\nB: b\r\nC: c\r\n\r\n",
+            b"GET / HTTP/1.1\r\nA: 0123456789012345678901234567890123456789012345678901234567890123\r\n\
+              B: 0123456789012345678901234567890123456789012345678901234567890123\r\n\r\n",
+            b"PUT / HTTP/1.1\r\nContent-Length: 17\r\n\r\n",
+            b"PUT / HTTP/1.1\r\nContent-Length: 99999999999999999999\r\n\r\n",
+            b"PUT / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+              8\r\n01234567\r\n9\r\n012345678\r\n0\r\n\r\n",
+        ];
+        let expected_statuses = [
+            "HTTP/1.1 414 URI Too Long",
+            "HTTP/1.1 431 Request Header Fields Too Large",
+            "HTTP/1.1 431 Request Header Fields Too Large",
+            "HTTP/1.1 413 Payload Too Large",
+            "HTTP/1.1 413 Payload Too Large",
+            "HTTP/1.1 413 Payload Too Large",
+        ];
+        for (request, expected_status) in requests.iter().zip(expected_statuses.iter()) {
+            let mut socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+            assert!(server.requests().unwrap().is_empty());
+
+            socket.write_all(request).unwrap();
+            assert!(server.requests().unwrap().is_empty());
+            assert!(server.requests().unwrap().is_empty());
+            let mut buf: [u8; 1024] = [0; 1024];
+            let bytes_read = socket.read(&mut buf[..]).unwrap();
+            assert!(String::from_utf8_lossy(&buf[..bytes_read]).starts_with(expected_status));
+        }
+
+        // Requests within the limits are still accepted.
+        let mut socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        socket
+            .write_all(b"PUT / HTTP/1.1\r\nContent-Length: 16\r\n\r\n0123456789012345")
+            .unwrap();
+        let mut req_vec = server.requests().unwrap();
+        let mut request = req_vec.remove(0).request;
+        assert_eq!(request.body().unwrap().as_slice(), b"0123456789012345");
+    }
+
+    #[test]
+    fn test_wait_long_lines() {
+        let path_to_socket = get_temp_socket_file();
+
+        let mut server = HttpServer::new_uds(path_to_socket.as_path()).unwrap();
+        server.start_server().unwrap();
+
+        let mut socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+
+        // Lines longer than the initial buffer are accepted up to the limits.
+        let path = format!("/{}", "a".repeat(3000));
+        let cookie = "b".repeat(5000);
+        socket
+            .write_all(format!("GET {} HTTP/1.1\r\nCookie: {}\r\n\r\n", path, cookie).as_bytes())
+            .unwrap();
+        let mut req_vec = vec![];
+        while req_vec.is_empty() {
+            req_vec = server.requests().unwrap();
+        }
+        let request = req_vec.remove(0).request;
+        assert_eq!(request.uri().get_abs_path(), path);
+        assert_eq!(request.headers.header_line("Cookie"), Some(&cookie));
+
+        // Subsequent requests on the same connection are still parsed.
+        socket.write_all(b"GET /short HTTP/1.1\r\n\r\n").unwrap();
+        let mut req_vec = server.requests().unwrap();
+        assert_eq!(req_vec.remove(0).request.uri().get_abs_path(), "/short");
+
+        // A request line longer than the default limit is rejected.
+        let mut socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        socket
+            .write_all(format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(9000)).as_bytes())
+            .unwrap();
+        while !server
+            .connections
+            .values()
+            .any(|c| c.connection.pending_write())
+        {
+            assert!(server.requests().unwrap().is_empty());
+        }
+        assert!(server.requests().unwrap().is_empty());
+        let mut buf: [u8; 1024] = [0; 1024];
+        let bytes_read = socket.read(&mut buf[..]).unwrap();
+        assert!(
+            String::from_utf8_lossy(&buf[..bytes_read]).starts_with("HTTP/1.1 414 URI Too Long")
+        );
+    }
+
+    #[test]
+    fn test_wait_keep_alive() {
+        let path_to_socket = get_temp_socket_file();
+
+        let mut server = HttpServer::new_uds(path_to_socket.as_path()).unwrap();
+        server.start_server().unwrap();
+
+        // HTTP/1.0 connections persist only if the client asks for it.
+        let mut socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        socket
+            .write_all(b"GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n")
+            .unwrap();
+        let mut req_vec = server.requests().unwrap();
+        server
+            .respond(
+                req_vec
+                    .remove(0)
+                    .process(|_request| Response::new(Version::Http10, StatusCode::NoContent)),
+            )
+            .unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        let expected_response = b"HTTP/1.0 204 No Content\r\n\
+                                  Connection: keep-alive\r\n\r\n";
+        let mut buf = [0u8; 1024];
+        let bytes_read = socket.read(&mut buf[..]).unwrap();
+        assert_eq!(&buf[..bytes_read], &expected_response[..]);
+
+        socket.write_all(b"GET / HTTP/1.0\r\n\r\n").unwrap();
+        let mut req_vec = server.requests().unwrap();
+        server
+            .respond(
+                req_vec
+                    .remove(0)
+                    .process(|_request| Response::new(Version::Http10, StatusCode::NoContent)),
+            )
+            .unwrap();
+        while server.connections.len() == 1 {
+            assert!(server.requests().unwrap().is_empty());
+        }
+        let expected_response = b"HTTP/1.0 204 No Content\r\n\
+                                  Connection: close\r\n\r\n";
+        let bytes_read = socket.read(&mut buf[..]).unwrap();
+        assert_eq!(&buf[..bytes_read], &expected_response[..]);
+        assert_eq!(socket.read(&mut buf[..]).unwrap(), 0);
+
+        // Requests pipelined after `Connection: close` are not processed.
+        let mut socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        socket
+            .write_all(
+                b"GET /first HTTP/1.1\r\nConnection: close\r\n\r\n\
+                  GET /second HTTP/1.1\r\n\r\n",
+            )
+            .unwrap();
+        let mut req_vec = server.requests().unwrap();
+        assert_eq!(req_vec.len(), 1);
+        let server_request = req_vec.remove(0);
+        assert_eq!(server_request.request.uri().get_abs_path(), "/first");
+        server
+            .respond(
+                server_request.process(|_request| Response::new(Version::Http11, StatusCode::OK)),
+            )
+            .unwrap();
+        while server.connections.len() == 1 {
+            assert!(server.requests().unwrap().is_empty());
+        }
+        let bytes_read = socket.read(&mut buf[..]).unwrap();
+        assert!(String::from_utf8_lossy(&buf[..bytes_read]).contains("Connection: close\r\n"));
+        assert_eq!(socket.read(&mut buf[..]).unwrap(), 0);
+
+        // A handler can close an HTTP/1.1 connection.
+        let mut socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        socket.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        let mut req_vec = server.requests().unwrap();
+        server
+            .respond(req_vec.remove(0).process(|_request| {
+                let mut response = Response::new(Version::Http11, StatusCode::OK);
+                response.with_connection_close();
+                response
+            }))
+            .unwrap();
+        while server.connections.len() == 1 {
+            assert!(server.requests().unwrap().is_empty());
+        }
+        let bytes_read = socket.read(&mut buf[..]).unwrap();
+        assert!(String::from_utf8_lossy(&buf[..bytes_read]).contains("Connection: close\r\n"));
+        assert_eq!(socket.read(&mut buf[..]).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_wait_stale_request_id() {
+        let path_to_socket = get_temp_socket_file();
+
+        let mut server = HttpServer::new_uds(path_to_socket.as_path()).unwrap();
+        server.start_server().unwrap();
+
+        let mut first_socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        first_socket.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        let mut req_vec = server.requests().unwrap();
+        let first_request = req_vec.remove(0);
+        first_socket.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        let mut req_vec = server.requests().unwrap();
+        let second_request = req_vec.remove(0);
+        assert_eq!(first_request.id(), first_request.id());
+        assert_ne!(first_request.id(), second_request.id());
+
+        // Once the connection is gone, the file descriptor can be given to a new
+        // client, which must not receive responses meant for the old one.
+        first_socket.shutdown(std::net::Shutdown::Both).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        let ok_response = |_request: &Request| Response::new(Version::Http11, StatusCode::OK);
+        server.respond(first_request.process(ok_response)).unwrap();
+        server.respond(second_request.process(ok_response)).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        assert!(server.connections.is_empty());
+
+        let mut second_socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+        second_socket.set_nonblocking(true).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        assert_eq!(server.connections.len(), 1);
+        match server.respond(first_request.process(ok_response)) {
+            Err(ServerError::ConnectionGone) => {}
+            _ => panic!("The response should not be delivered."),
+        }
+        server
+            .enqueue_responses(vec![second_request.process(ok_response)])
+            .unwrap();
+        assert!(!server
+            .connections
+            .values()
+            .any(|c| c.connection.pending_write()));
+        let mut buf = [0u8; 1024];
+        assert!(second_socket.read(&mut buf[..]).is_err());
+    }
+
+    #[test]
+    fn test_wait_pipelined_responses_order() {
+        let path_to_socket = get_temp_socket_file();
+
+        let mut server = HttpServer::new_uds(path_to_socket.as_path()).unwrap();
+        server.start_server().unwrap();
+
+        let mut socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+        socket.set_nonblocking(true).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        socket
+            .write_all(
+                b"GET /a HTTP/1.1\r\n\r\n\
+                  GET /b HTTP/1.1\r\n\r\n\
+                  GET /c HTTP/1.1\r\n\r\n",
+            )
+            .unwrap();
+        let req_vec = server.requests().unwrap();
+        assert_eq!(req_vec.len(), 3);
+        for (sequence, server_request) in req_vec.iter().enumerate() {
+            assert_eq!(server_request.id().sequence(), sequence as u64);
+        }
+
+        let echo_path = |request: &Request| {
+            let mut response = Response::new(Version::Http11, StatusCode::OK);
+            response.with_body(request.uri().get_abs_path().as_bytes());
+            response
+        };
+        // The responses to the last requests are held back until the first one
+        // is answered.
+        server.respond(req_vec[2].process(echo_path)).unwrap();
+        server.respond(req_vec[1].process(echo_path)).unwrap();
+        // Answering the same request twice has no effect.
+        assert!(matches!(
+            server.respond(req_vec[1].process(echo_path)),
+            Err(ServerError::AlreadyAnswered)
+        ));
+        assert!(!server
+            .connections
+            .values()
+            .any(|c| c.connection.pending_write()));
+        let mut buf = [0u8; 1024];
+        assert!(socket.read(&mut buf[..]).is_err());
+
+        server.respond(req_vec[0].process(echo_path)).unwrap();
+        while server
+            .connections
+            .values()
+            .any(|c| c.connection.pending_write())
+        {
+            assert!(server.requests().unwrap().is_empty());
+        }
+        let expected_response = b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n/a\
+                                  HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n/b\
+                                  HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n/c";
+        let bytes_read = socket.read(&mut buf[..]).unwrap();
+        assert_eq!(&buf[..bytes_read], &expected_response[..]);
+        // Nor once the response was sent.
+        assert!(matches!(
+            server.respond(req_vec[0].process(echo_path)),
+            Err(ServerError::AlreadyAnswered)
+        ));
+        let client_connection = server.connections.values().next().unwrap();
+        assert_eq!(client_connection.in_flight_response_count, 0);
+    }
+
+    #[test]
+    fn test_wait_pipelined_expect_order() {
+        let path_to_socket = get_temp_socket_file();
+
+        let mut server = HttpServer::new_uds(path_to_socket.as_path()).unwrap();
+        server.start_server().unwrap();
+
+        let mut socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+        socket.set_nonblocking(true).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        socket
+            .write_all(
+                b"GET /a HTTP/1.1\r\n\r\n\
+                  PUT /b HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: 3\r\n\r\n",
+            )
+            .unwrap();
+        let req_vec = server.requests().unwrap();
+        assert_eq!(req_vec.len(), 1);
+        // The `100 Continue` is held back until the first request is answered.
+        assert!(!server
+            .connections
+            .values()
+            .any(|c| c.connection.pending_write()));
+
+        server
+            .respond(req_vec[0].process(|_request| Response::new(Version::Http11, StatusCode::OK)))
+            .unwrap();
+        while server
+            .connections
+            .values()
+            .any(|c| c.connection.pending_write())
+        {
+            assert!(server.requests().unwrap().is_empty());
+        }
+        let expected_response = b"HTTP/1.1 200 OK\r\n\r\n\
+                                  HTTP/1.1 100 Continue\r\n\r\n";
+        let mut buf = [0u8; 1024];
+        let bytes_read = socket.read(&mut buf[..]).unwrap();
+        assert_eq!(&buf[..bytes_read], &expected_response[..]);
+
+        socket.write_all(b"abc").unwrap();
+        let mut req_vec = vec![];
+        while req_vec.is_empty() {
+            req_vec = server.requests().unwrap();
+        }
+        assert_eq!(req_vec[0].request.body.as_ref().unwrap().stream, b"abc");
+    }
+
+    #[test]
+    fn test_wait_server_config() {
+        let path_to_socket = get_temp_socket_file();
+
+        let mut config = HttpServerConfig::default();
+        config
+            .with_max_connections(1)
+            .with_max_events(1)
+            .with_buffer_size(16)
+            .with_server_name(None)
+            .with_error_body_template("error: {message}".to_string());
+        let mut server = HttpServer::new_uds_with_config(path_to_socket.as_path(), config).unwrap();
+        server.start_server().unwrap();
+
+        let mut first_socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        let mut second_socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        let mut buf = [0u8; 1024];
+        let bytes_read = second_socket.read(&mut buf[..]).unwrap();
+        assert_eq!(
+            &buf[..bytes_read],
+            &b"HTTP/1.1 503 Service Unavailable\r\n\
+               Connection: close\r\n\
+               Content-Length: 32\r\n\r\n\
+               error: Too many open connections"[..]
+        );
+
+        // Lines longer than the initial buffer are still parsed.
+        first_socket
+            .write_all(b"GET /machine-config HTTP/1.1\r\n\r\nGET /vm\"1 HTTP/1.1\r\n\r\n")
+            .unwrap();
+        let mut req_vec = vec![];
+        while req_vec.is_empty() {
+            req_vec = server.requests().unwrap();
+        }
+        let server_request = req_vec.remove(0);
+        assert_eq!(
+            server_request.request.uri().get_abs_path(),
+            "/machine-config"
+        );
+        server
+            .respond(
+                server_request
+                    .process(|_request| Response::new(Version::Http11, StatusCode::NoContent)),
+            )
+            .unwrap();
+        while server.connections.len() == 1 {
+            assert!(server.requests().unwrap().is_empty());
+        }
+        let mut response_bytes = vec![];
+        first_socket.read_to_end(&mut response_bytes).unwrap();
+        let response_bytes = String::from_utf8(response_bytes).unwrap();
+        assert!(response_bytes.starts_with("HTTP/1.1 204 No Content\r\n\r\n"));
+        assert!(response_bytes.ends_with(
+            "\r\n\r\nerror: Request error: Invalid URI: URI contains a character which is not \
+             allowed.\nAll previous unanswered requests will be dropped."
+        ));
+        assert!(!response_bytes.contains("Server:"));
+    }
+
+    #[test]
+    fn test_wait_timeouts() {
+        let path_to_socket = get_temp_socket_file();
+
+        let mut config = HttpServerConfig::default();
+        config
+            .with_idle_timeout(Some(Duration::from_millis(50)))
+            .with_header_timeout(Some(Duration::from_millis(50)))
+            .with_body_timeout(Some(Duration::from_millis(50)));
+        let mut server = HttpServer::new_uds_with_config(path_to_socket.as_path(), config).unwrap();
+        server.start_server().unwrap();
+
+        // A connection on which nothing is sent is closed.
+        let mut idle_socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        while server.connections.len() == 1 {
+            assert!(server.requests().unwrap().is_empty());
+        }
+        let mut buf = [0u8; 1024];
+        assert_eq!(idle_socket.read(&mut buf[..]).unwrap(), 0);
+
+        // A connection which does not finish the header section of a request in
+        // time is answered with `408 Request Timeout`.
+        let mut slow_socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        slow_socket.write_all(b"GET /machine-config HTT").unwrap();
+        while server.connections.len() == 1 {
+            assert!(server.requests().unwrap().is_empty());
+        }
+        let mut response_bytes = vec![];
+        slow_socket.read_to_end(&mut response_bytes).unwrap();
+        assert_eq!(
+            &response_bytes[..],
+            &b"HTTP/1.1 408 Request Timeout\r\n\
+               Server: Firecracker API\r\n\
+               Connection: close\r\n\
+               Content-Length: 33\r\n\r\n\
+               { \"error\": \"Request timed out.\" }"[..]
+        );
+
+        // The same goes for the body of a request, whereas the answered requests
+        // before it are kept.
+        let mut slow_socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        slow_socket
+            .write_all(
+                b"GET /machine-config HTTP/1.1\r\n\r\n\
+                  PATCH /machine-config HTTP/1.1\r\n\
+                  Content-Length: 10\r\n\r\n\
+                  12",
+            )
+            .unwrap();
+        let mut req_vec = vec![];
+        while req_vec.is_empty() {
+            req_vec = server.requests().unwrap();
+        }
+        let server_request = req_vec.remove(0);
+        server
+            .respond(
+                server_request
+                    .process(|_request| Response::new(Version::Http11, StatusCode::NoContent)),
+            )
+            .unwrap();
+        while server.connections.len() == 1 {
+            assert!(server.requests().unwrap().is_empty());
+        }
+        let mut response_bytes = vec![];
+        slow_socket.read_to_end(&mut response_bytes).unwrap();
+        let response_bytes = String::from_utf8(response_bytes).unwrap();
+        assert!(response_bytes.starts_with("HTTP/1.1 204 No Content\r\n\r\n"));
+        assert!(response_bytes.contains("HTTP/1.1 408 Request Timeout\r\n"));
+    }
+
+    #[test]
+    fn test_wait_shutdown() {
+        let path_to_socket = get_temp_socket_file();
+
+        let mut server = HttpServer::new_uds(path_to_socket.as_path()).unwrap();
+        server.start_server().unwrap();
+
+        let mut busy_socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        let mut idle_socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+
+        busy_socket
+            .write_all(b"GET /machine-config HTTP/1.1\r\n\r\n")
+            .unwrap();
+        let mut req_vec = vec![];
+        while req_vec.is_empty() {
+            req_vec = server.requests().unwrap();
+        }
+        let server_request = req_vec.remove(0);
+
+        // The idle connection is closed right away, whereas the other one waits for
+        // its response.
+        server.shutdown(None).unwrap();
+        assert!(!server.is_drained());
+        assert_eq!(server.connections.len(), 1);
+        let mut buf = [0u8; 1024];
+        assert_eq!(idle_socket.read(&mut buf[..]).unwrap(), 0);
+
+        // Requests sent during shutdown are not parsed.
+        busy_socket
+            .write_all(b"GET /machine-config HTTP/1.1\r\n\r\n")
+            .unwrap();
+        assert!(server.requests().unwrap().is_empty());
+
+        server
+            .respond(
+                server_request
+                    .process(|_request| Response::new(Version::Http11, StatusCode::NoContent)),
+            )
+            .unwrap();
+        while !server.is_drained() {
+            assert!(server.requests().unwrap().is_empty());
+        }
+        let mut response_bytes = vec![];
+        busy_socket.read_to_end(&mut response_bytes).unwrap();
+        assert_eq!(
+            &response_bytes[..],
+            &b"HTTP/1.1 204 No Content\r\nConnection: close\r\n\r\n"[..]
+        );
+        assert!(server.requests().unwrap().is_empty());
+
+        // Connections are dropped once the deadline expires.
+        let path_to_socket = get_temp_socket_file();
+        let mut server = HttpServer::new_uds(path_to_socket.as_path()).unwrap();
+        server.start_server().unwrap();
+
+        let mut socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        socket
+            .write_all(b"GET /machine-config HTTP/1.1\r\n\r\n")
+            .unwrap();
+        let mut req_vec = vec![];
+        while req_vec.is_empty() {
+            req_vec = server.requests().unwrap();
+        }
+        let server_request = req_vec.remove(0);
+
+        server.shutdown(Some(Duration::from_millis(50))).unwrap();
+        while !server.is_drained() {
+            assert!(server.requests().unwrap().is_empty());
+        }
+        assert_eq!(socket.read(&mut buf[..]).unwrap(), 0);
+        let server_response = server_request
+            .process(|_request| Response::new(Version::Http11, StatusCode::NoContent));
+        assert!(matches!(
+            server.respond(server_response),
+            Err(ServerError::ConnectionGone)
+        ));
+    }
+
+    #[test]
+    fn test_wait_router() {
+        let path_to_socket = get_temp_socket_file();
+
+        let mut config = HttpServerConfig::default();
+        config
+            .with_server_name(Some("Example".to_string()))
+            .with_error_body_template("error: {message}".to_string());
+        let mut server = HttpServer::new_uds_with_config(path_to_socket.as_path(), config).unwrap();
+        server.start_server().unwrap();
+
+        let mut router = Router::new();
+        router
+            .with_server_name(Some("Example".to_string()))
+            .with_error_body_template("error: {message}".to_string())
+            .route(Method::Get, "/vms/{id}", |_request, params| {
+                let mut response = Response::new(Version::Http11, StatusCode::OK);
+                response.with_body(params.get("id").unwrap().as_bytes());
+                response
+            })
+            .route(Method::Delete, "/vms/{id}", |_request, _params| {
+                Response::new(Version::Http11, StatusCode::NoContent)
+            });
+
+        let mut socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        socket
+            .write_all(
+                b"GET /vms/42 HTTP/1.1\r\n\r\n\
+                  HEAD /vms/42 HTTP/1.1\r\n\r\n\
+                  GET /vms/4%2F2 HTTP/1.1\r\n\r\n\
+                  PUT /vms/42 HTTP/1.1\r\n\r\n\
+                  GET /machine-config HTTP/1.1\r\n\r\n",
+            )
+            .unwrap();
+        let mut req_vec = vec![];
+        while req_vec.len() < 5 {
+            req_vec.append(&mut server.requests().unwrap());
+        }
+        for server_request in req_vec {
+            server
+                .respond(server_request.process(|request| router.handle(request)))
+                .unwrap();
+        }
+        while server
+            .connections
+            .values()
+            .any(|c| c.connection.pending_write())
+        {
+            assert!(server.requests().unwrap().is_empty());
+        }
+
+        let mut buf = [0u8; 1024];
+        let mut response_bytes = vec![];
+        while !response_bytes.ends_with(b"error: No route matches the path.") {
+            let bytes_read = socket.read(&mut buf[..]).unwrap();
+            response_bytes.extend_from_slice(&buf[..bytes_read]);
+        }
+        assert_eq!(
+            String::from_utf8(response_bytes).unwrap(),
+            "HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n42\
+             HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n\
+             HTTP/1.1 200 OK\r\nContent-Length: 3\r\n\r\n4/2\
+             HTTP/1.1 405 Method Not Allowed\r\n\
+             Server: Example\r\n\
+             Allow: GET, DELETE, HEAD\r\n\
+             Content-Length: 46\r\n\r\n\
+             error: The method is not allowed for the path.\
+             HTTP/1.1 404 Not Found\r\n\
+             Server: Example\r\n\
+             Content-Length: 33\r\n\r\n\
+             error: No route matches the path."
+        );
+    }
+
+    #[test]
+    fn test_wait_peer_info() {
+        let path_to_socket = get_temp_socket_file();
+
+        let mut server = HttpServer::new_uds(path_to_socket.as_path()).unwrap();
+        server.start_server().unwrap();
+
+        let mut socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        socket
+            .write_all(b"GET /machine-config HTTP/1.1\r\n\r\n")
+            .unwrap();
+        let mut req_vec = vec![];
+        while req_vec.is_empty() {
+            req_vec = server.requests().unwrap();
+        }
+        // Safe because these calls have no arguments and always succeed.
+        let (uid, gid) = unsafe { (libc::geteuid(), libc::getegid()) };
+        assert_eq!(
+            req_vec[0].peer(),
+            PeerInfo::Uds(PeerCredentials {
+                pid: std::process::id() as libc::pid_t,
+                uid,
+                gid,
+            })
+        );
+        assert!(req_vec[0].peer().addr().is_none());
+
+        let (mut server, port) = get_temp_tcp_server().unwrap();
+        server.start_server().unwrap();
+
+        let mut socket = TcpStream::connect(SocketAddr::new(LOCAL_ADDR, port)).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        socket
+            .write_all(b"GET /machine-config HTTP/1.1\r\n\r\n")
+            .unwrap();
+        let mut req_vec = vec![];
+        while req_vec.is_empty() {
+            req_vec = server.requests().unwrap();
+        }
+        assert_eq!(
+            req_vec[0].peer(),
+            PeerInfo::Tcp(socket.local_addr().unwrap())
+        );
+        assert!(req_vec[0].peer().credentials().is_none());
+    }
+
+    #[test]
+    fn test_wait_allowed_peers() {
+        // Safe because these calls have no arguments and always succeed.
+        let (uid, gid) = unsafe { (libc::geteuid(), libc::getegid()) };
+
+        // Clients which are not in the list are turned away.
+        let path_to_socket = get_temp_socket_file();
+        let mut allowed_peers = PeerAllowList::new();
+        allowed_peers.allow_uid(uid + 1).allow_gid(gid + 1);
+        let mut config = HttpServerConfig::default();
+        config.with_allowed_peers(Some(allowed_peers));
+        let mut server = HttpServer::new_uds_with_config(path_to_socket.as_path(), config).unwrap();
+        server.start_server().unwrap();
+
+        let mut socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        assert!(server.connections.is_empty());
+        let mut response_bytes = vec![];
+        socket.read_to_end(&mut response_bytes).unwrap();
+        let response_bytes = String::from_utf8(response_bytes).unwrap();
+        assert!(response_bytes.starts_with("HTTP/1.1 403 Forbidden\r\n"));
+        assert!(response_bytes.contains("Connection: close\r\n"));
+
+        // Clients in the list are served, subject to the rules of the routes.
+        let path_to_socket = get_temp_socket_file();
+        let mut allowed_peers = PeerAllowList::new();
+        allowed_peers.allow_uid(uid);
+        let mut config = HttpServerConfig::default();
+        config.with_allowed_peers(Some(allowed_peers.clone()));
+        let mut server = HttpServer::new_uds_with_config(path_to_socket.as_path(), config).unwrap();
+        server.start_server().unwrap();
+
+        let mut other_peers = PeerAllowList::new();
+        other_peers.allow_uid(uid + 1);
+        let mut router = Router::new();
+        router
+            .route(Method::Get, "/machine-config", |_request, _params| {
+                Response::new(Version::Http11, StatusCode::NoContent)
+            })
+            .route(Method::Put, "/machine-config", |_request, _params| {
+                Response::new(Version::Http11, StatusCode::NoContent)
+            })
+            .restrict(Method::Get, "/machine-config", allowed_peers)
+            .restrict(Method::Put, "/machine-config", other_peers);
+
+        let mut socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        assert_eq!(server.connections.len(), 1);
+        socket
+            .write_all(
+                b"GET /machine-config HTTP/1.1\r\n\r\n\
+                  PUT /machine-config HTTP/1.1\r\n\r\n",
+            )
+            .unwrap();
+        let mut req_vec = vec![];
+        while req_vec.len() < 2 {
+            req_vec.append(&mut server.requests().unwrap());
+        }
+        let statuses: Vec<StatusCode> = req_vec
+            .iter()
+            .map(|server_request| {
+                router
+                    .handle_from(server_request.inner(), &server_request.peer())
+                    .status()
+            })
+            .collect();
+        assert_eq!(statuses, vec![StatusCode::NoContent, StatusCode::Forbidden]);
+    }
+
+    #[test]
+    fn test_wait_access_log() {
+        let path_to_socket = get_temp_socket_file();
+
+        let mut server = HttpServer::new_uds(path_to_socket.as_path()).unwrap();
+        let log = SharedBuffer::default();
+        server.set_access_log(Some(AccessLog::new(log.clone(), AccessLogFormat::Common)));
+        server.start_server().unwrap();
+
+        let mut socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        socket
+            .write_all(b"GET /machine-config HTTP/1.1\r\n\r\n")
+            .unwrap();
+        let mut req_vec = vec![];
+        while req_vec.is_empty() {
+            req_vec = server.requests().unwrap();
+        }
+        server
+            .respond(
+                req_vec
+                    .remove(0)
+                    .process(|_request| Response::new(Version::Http11, StatusCode::NoContent)),
+            )
+            .unwrap();
+        socket.write_all(b"GET /vm\"1 HTTP/1.1\r\n\r\n").unwrap();
+        while server.connections.len() == 1 {
+            assert!(server.requests().unwrap().is_empty());
+        }
+
+        // Safe because this call has no arguments and always succeeds.
+        let uid = unsafe { libc::geteuid() };
+        let lines = log.lines();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with(&format!("- - {} [", uid)));
+        assert!(lines[0].contains(" +0000] \"GET /machine-config HTTP/1.1\" 204 27 "));
+        assert!(lines[1].contains(" +0000] \"-\" 400 "));
+
+        // The same goes for JSON lines and TCP clients.
+        let (mut server, port) = get_temp_tcp_server().unwrap();
+        let log = SharedBuffer::default();
+        server.set_access_log(Some(AccessLog::new(log.clone(), AccessLogFormat::Json)));
+        server.start_server().unwrap();
+
+        let mut socket = TcpStream::connect(SocketAddr::new(LOCAL_ADDR, port)).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        socket
+            .write_all(b"GET /machine-config?a=%22 HTTP/1.0\r\n\r\n")
+            .unwrap();
+        let mut req_vec = vec![];
+        while req_vec.is_empty() {
+            req_vec = server.requests().unwrap();
+        }
+        server
+            .respond(req_vec.remove(0).process(|_request| {
+                let mut response = Response::new(Version::Http10, StatusCode::OK);
+                response.with_body(b"body");
+                response
+            }))
+            .unwrap();
+        while server.connections.len() == 1 {
+            assert!(server.requests().unwrap().is_empty());
+        }
+
+        let lines = log.lines();
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].starts_with("{\"time\":\""));
+        assert!(lines[0].contains(&format!(
+            "Z\",\"peer\":\"{}\",\"method\":\"GET\",\"uri\":\"/machine-config?a=%22\",\
+             \"version\":\"HTTP/1.0\",\"status\":200,\"bytes\":61,\"latency_us\":",
+            socket.local_addr().unwrap()
+        )));
+        assert!(lines[0].ends_with('}'));
+    }
+
+    #[test]
+    fn test_wait_metrics() {
+        let path_to_socket = get_temp_socket_file();
+
+        let mut config = HttpServerConfig::default();
+        config.with_max_connections(1);
+        let mut server = HttpServer::new_uds_with_config(path_to_socket.as_path(), config).unwrap();
+        server.start_server().unwrap();
+
+        let mut socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        let _rejected_socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+
+        socket
+            .write_all(b"GET /machine-config HTTP/1.1\r\n\r\n")
+            .unwrap();
+        let mut req_vec = vec![];
+        while req_vec.is_empty() {
+            req_vec = server.requests().unwrap();
+        }
+        server
+            .respond(
+                req_vec
+                    .remove(0)
+                    .process(|_request| Response::new(Version::Http11, StatusCode::NoContent)),
+            )
+            .unwrap();
+        while server
+            .connections
+            .values()
+            .any(|c| c.connection.pending_write())
+        {
+            assert!(server.requests().unwrap().is_empty());
+        }
+
+        let metrics = server.metrics();
+        assert_eq!(metrics.accepted_connections, 1);
+        assert_eq!(metrics.rejected_connections, 1);
+        assert_eq!(metrics.open_connections, 1);
+        assert_eq!(metrics.bytes_read, 32);
+        assert_eq!(metrics.bytes_written, 27);
+        assert_eq!(metrics.requests.get(&("GET".to_string(), 204)), Some(&1));
+        assert!(metrics.parse_errors.is_empty());
+
+        socket.write_all(b"PROPFIND / HTTP/1.1\r\n\r\n").unwrap();
+        let mut req_vec = vec![];
+        while req_vec.is_empty() {
+            req_vec = server.requests().unwrap();
+        }
+        server
+            .respond(
+                req_vec
+                    .remove(0)
+                    .process(|_request| Response::new(Version::Http11, StatusCode::NoContent)),
+            )
+            .unwrap();
+        while server
+            .connections
+            .values()
+            .any(|c| c.connection.pending_write())
+        {
+            assert!(server.requests().unwrap().is_empty());
+        }
+        let metrics = server.metrics();
+        assert_eq!(metrics.requests.get(&("OTHER".to_string(), 204)), Some(&1));
+
+        socket.write_all(b"GET /vm\"1 HTTP/1.1\r\n\r\n").unwrap();
+        while server.connections.len() == 1 {
+            assert!(server.requests().unwrap().is_empty());
+        }
+        let metrics = server.metrics();
+        assert_eq!(metrics.open_connections, 0);
+        assert_eq!(metrics.parse_errors.get("InvalidRequest"), Some(&1));
+        // The responses sent by the server on its own are not counted as requests.
+        assert_eq!(metrics.requests.len(), 2);
+
+        let text = metrics.to_prometheus();
+        assert!(text.starts_with(
+            "# HELP micro_http_connections_accepted_total Connections accepted.\n\
+             # TYPE micro_http_connections_accepted_total counter\n\
+             micro_http_connections_accepted_total 1\n"
+        ));
+        assert!(text.contains("\nmicro_http_connections_rejected_total 1\n"));
+        assert!(text.contains("\nmicro_http_parse_errors_total{error=\"InvalidRequest\"} 1\n"));
+        assert!(text.contains("\nmicro_http_requests_total{method=\"GET\",status=\"204\"} 1\n"));
+        assert!(text.contains("\nmicro_http_requests_total{method=\"OTHER\",status=\"204\"} 1\n"));
+        assert!(text.contains("\nmicro_http_read_bytes_total 77\n"));
+        assert!(text.ends_with(
+            "# TYPE micro_http_open_connections gauge\nmicro_http_open_connections 0\n"
+        ));
+
+        // A connection closed by the client is no longer counted as open, even
+        // though it is kept until its request is answered.
+        let mut socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+        socket
+            .write_all(b"GET /machine-config HTTP/1.1\r\n\r\n")
+            .unwrap();
+        let mut req_vec = vec![];
+        while req_vec.is_empty() {
+            req_vec = server.requests().unwrap();
+        }
+        assert_eq!(server.metrics().open_connections, 1);
+        drop(socket);
+        while server
+            .connections
+            .values()
+            .any(|c| c.state != ClientConnectionState::Closed)
+        {
+            assert!(server.requests().unwrap().is_empty());
+        }
+        assert_eq!(server.connections.len(), 1);
+        assert_eq!(server.metrics().open_connections, 0);
+    }
+
+    #[test]
+    fn test_wait_overload_policies() {
+        // Safe because these calls have no arguments and always succeed.
+        let (uid, gid) = unsafe { (libc::geteuid(), libc::getegid()) };
+
+        // Rejected clients are told when to retry.
+        let path_to_socket = get_temp_socket_file();
+        let mut config = HttpServerConfig::default();
+        config
+            .with_max_connections(1)
+            .with_retry_after(Some(Duration::from_millis(2500)));
+        let mut server = HttpServer::new_uds_with_config(path_to_socket.as_path(), config).unwrap();
+        server.start_server().unwrap();
+
+        let _first_socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        let mut second_socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        let response = Response::receive(&mut second_socket).unwrap();
+        assert_eq!(response.status(), StatusCode::ServiceUnavailable);
+        assert_eq!(response.header_line("Retry-After").unwrap(), "3");
+        assert_eq!(server.metrics().rejected_connections, 1);
+
+        // Idle connections make room for new clients.
+        let path_to_socket = get_temp_socket_file();
+        let mut config = HttpServerConfig::default();
+        config
+            .with_max_connections(2)
+            .with_overload_policy(OverloadPolicy::EvictIdle);
+        let mut server = HttpServer::new_uds_with_config(path_to_socket.as_path(), config).unwrap();
+        server.start_server().unwrap();
+
+        let mut first_socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        let mut second_socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        second_socket
+            .write_all(b"GET /machine-config HTTP/1.1\r\n\r\n")
+            .unwrap();
+        let mut req_vec = vec![];
+        while req_vec.is_empty() {
+            req_vec = server.requests().unwrap();
+        }
+        // The first connection is the only idle one.
+        let mut third_socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        let mut buf = [0u8; 16];
+        assert_eq!(first_socket.read(&mut buf[..]).unwrap(), 0);
+        assert_eq!(server.connections.len(), 2);
+        assert_eq!(server.metrics().evicted_connections, 1);
+
+        // Clients are rejected when no connection is idle.
+        third_socket
+            .write_all(b"GET /machine-config HTTP/1.1\r\n\r\n")
+            .unwrap();
+        let mut more_requests = vec![];
+        while more_requests.is_empty() {
+            more_requests = server.requests().unwrap();
+        }
+        let mut fourth_socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        let response = Response::receive(&mut fourth_socket).unwrap();
+        assert_eq!(response.status(), StatusCode::ServiceUnavailable);
+        assert!(response.header_line("Retry-After").is_none());
+        assert_eq!(server.metrics().evicted_connections, 1);
+        assert_eq!(server.metrics().rejected_connections, 1);
+
+        // The last slot is kept for the privileged clients.
+        for (privileged_uid, accepted_count) in [(uid + 1, 1), (uid, 2)].iter() {
+            let path_to_socket = get_temp_socket_file();
+            let mut peers = PeerAllowList::new();
+            peers.allow_uid(*privileged_uid).allow_gid(gid + 1);
+            let mut config = HttpServerConfig::default();
+            config
+                .with_max_connections(2)
+                .with_overload_policy(OverloadPolicy::Reserve {
+                    connections: 1,
+                    peers,
+                });
+            let mut server =
+                HttpServer::new_uds_with_config(path_to_socket.as_path(), config).unwrap();
+            server.start_server().unwrap();
+
+            let mut sockets = vec![];
+            for _ in 0..3 {
+                sockets.push(UnixStream::connect(path_to_socket.as_path()).unwrap());
+                assert!(server.requests().unwrap().is_empty());
+            }
+            assert_eq!(server.metrics().accepted_connections, *accepted_count);
+            assert_eq!(server.metrics().rejected_connections, 3 - *accepted_count);
+        }
+
+        // Clients wait until a slot frees.
+        let path_to_socket = get_temp_socket_file();
+        let mut config = HttpServerConfig::default();
+        config
+            .with_max_connections(1)
+            .with_overload_policy(OverloadPolicy::PauseAccept);
+        let mut server = HttpServer::new_uds_with_config(path_to_socket.as_path(), config).unwrap();
+        server.start_server().unwrap();
+
+        let first_socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        let mut second_socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        assert!(server.accept_paused);
+        assert_eq!(server.metrics().accepted_connections, 1);
+
+        drop(first_socket);
+        while server.metrics().accepted_connections == 1 {
+            assert!(server.requests().unwrap().is_empty());
+        }
+        assert!(!server.accept_paused);
+        assert_eq!(server.metrics().rejected_connections, 0);
+        second_socket
+            .write_all(b"GET /machine-config HTTP/1.1\r\n\r\n")
+            .unwrap();
+        let mut req_vec = vec![];
+        while req_vec.is_empty() {
+            req_vec = server.requests().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_wait_evict_idle_forbidden_peer() {
+        // Safe because these calls have no arguments and always succeed.
+        let uid = unsafe { libc::geteuid() };
+
+        // Clients connecting over TCP have no credentials, so they are turned away.
+        let path_to_socket = get_temp_socket_file();
+        let mut allowed_peers = PeerAllowList::new();
+        allowed_peers.allow_uid(uid);
+        let mut config = HttpServerConfig::default();
+        config
+            .with_max_connections(1)
+            .with_overload_policy(OverloadPolicy::EvictIdle)
+            .with_allowed_peers(Some(allowed_peers));
+        let mut server = HttpServer::new_uds_with_config(path_to_socket.as_path(), config).unwrap();
+        let port = (36000..=u16::MAX)
+            .find(|port| {
+                server
+                    .add_tcp_listener(SocketAddr::new(LOCAL_ADDR, *port))
+                    .is_ok()
+            })
+            .unwrap();
+        server.start_server().unwrap();
+
+        let mut idle_socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        assert_eq!(server.connections.len(), 1);
+
+        // A client which is not allowed to connect does not take the place of
+        // the idle connection.
+        let mut forbidden_socket = TcpStream::connect(SocketAddr::new(LOCAL_ADDR, port)).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        let response = Response::receive(&mut forbidden_socket).unwrap();
+        assert_eq!(response.status(), StatusCode::Forbidden);
+        assert_eq!(server.connections.len(), 1);
+        assert_eq!(server.metrics().evicted_connections, 0);
+
+        idle_socket
+            .write_all(b"GET /machine-config HTTP/1.1\r\n\r\n")
+            .unwrap();
+        let mut req_vec = vec![];
+        while req_vec.is_empty() {
+            req_vec = server.requests().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_wait_multiple_listeners() {
+        let first_path = get_temp_socket_file();
+        let second_path = get_temp_socket_file();
+        let mut server = HttpServer::new_uds(first_path.as_path()).unwrap();
+        // Listeners can be added both before and after the server is started.
+        let second_listener = server.add_uds_listener(second_path.as_path()).unwrap();
+        server.start_server().unwrap();
+        let (third_listener, port) = (36000..=u16::MAX)
+            .find_map(|port| {
+                server
+                    .add_tcp_listener(SocketAddr::new(LOCAL_ADDR, port))
+                    .ok()
+                    .map(|listener| (listener, port))
+            })
+            .unwrap();
+        assert_eq!(second_listener.index(), 1);
+        assert_eq!(third_listener.index(), 2);
+
+        let mut first_socket = UnixStream::connect(first_path.as_path()).unwrap();
+        let mut second_socket = UnixStream::connect(second_path.as_path()).unwrap();
+        let mut third_socket = TcpStream::connect(SocketAddr::new(LOCAL_ADDR, port)).unwrap();
+        while server.connections.len() < 3 {
+            assert!(server.requests().unwrap().is_empty());
+        }
+
+        for socket in [
+            &mut first_socket as &mut dyn Write,
+            &mut second_socket,
+            &mut third_socket,
+        ]
+        .iter_mut()
+        {
+            socket
+                .write_all(b"GET /machine-config HTTP/1.1\r\n\r\n")
+                .unwrap();
+        }
+        let mut req_vec = vec![];
+        while req_vec.len() < 3 {
+            req_vec.append(&mut server.requests().unwrap());
+        }
+        let mut listeners: Vec<usize> = req_vec
+            .iter()
+            .map(|request| request.listener().index())
+            .collect();
+        listeners.sort_unstable();
+        assert_eq!(listeners, vec![0, 1, 2]);
+        let tcp_request = req_vec
+            .iter()
+            .find(|request| request.listener() == third_listener)
+            .unwrap();
+        assert!(tcp_request.peer().addr().is_some());
+    }
+
+    #[test]
+    fn test_wait_workers() {
+        let path_to_socket = get_temp_socket_file();
+        let mut server = HttpServer::new_uds(path_to_socket.as_path()).unwrap();
+        server.start_server().unwrap();
+
+        let (release_sender, release_receiver) = std::sync::mpsc::channel::<()>();
+        let release_receiver = std::sync::Mutex::new(release_receiver);
+        server
+            .start_workers(2, move |request| {
+                match request.inner().uri().get_abs_path() {
+                    "/slow" => release_receiver.lock().unwrap().recv().unwrap(),
+                    "/panic" => panic!("handler failure"),
+                    _ => {}
+                }
+                Response::new(Version::Http11, StatusCode::NoContent)
+            })
+            .unwrap();
+
+        // Reads a response from `socket`, letting the server run in the meantime.
+        fn receive_response(server: &mut HttpServer, socket: &mut UnixStream) -> String {
+            let mut buf = [0u8; 1024];
+            loop {
+                assert!(server.requests().unwrap().is_empty());
+                match socket.read(&mut buf[..]) {
+                    Ok(bytes_read) => {
+                        return String::from_utf8(buf[..bytes_read].to_vec()).unwrap()
+                    }
+                    Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+                    Err(e) => panic!("{}", e),
+                }
+            }
+        }
+
+        let mut slow_socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+        slow_socket.set_nonblocking(true).unwrap();
+        let mut fast_socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+        fast_socket.set_nonblocking(true).unwrap();
+        while server.connections.len() < 2 {
+            assert!(server.requests().unwrap().is_empty());
+        }
+        slow_socket
+            .write_all(b"GET /slow HTTP/1.1\r\n\r\n")
+            .unwrap();
+        fast_socket
+            .write_all(b"GET /fast HTTP/1.1\r\n\r\n")
+            .unwrap();
+
+        // The slow handler does not hold up the other connection.
+        assert_eq!(
+            receive_response(&mut server, &mut fast_socket),
+            "HTTP/1.1 204 No Content\r\n\r\n"
+        );
+        release_sender.send(()).unwrap();
+        assert_eq!(
+            receive_response(&mut server, &mut slow_socket),
+            "HTTP/1.1 204 No Content\r\n\r\n"
+        );
+
+        // A failing handler gets its request answered and keeps its worker.
+        fast_socket
+            .write_all(b"GET /panic HTTP/1.1\r\n\r\n")
+            .unwrap();
+        assert!(receive_response(&mut server, &mut fast_socket)
+            .starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+        for _ in 0..2 {
+            fast_socket
+                .write_all(b"GET /fast HTTP/1.1\r\n\r\n")
+                .unwrap();
+            assert_eq!(
+                receive_response(&mut server, &mut fast_socket),
+                "HTTP/1.1 204 No Content\r\n\r\n"
+            );
+        }
+    }
+
+    #[test]
+    fn test_wait_no_workers() {
+        let path_to_socket = get_temp_socket_file();
+        let mut server = HttpServer::new_uds(path_to_socket.as_path()).unwrap();
+        server.start_server().unwrap();
+
+        // Without workers the requests would never be answered.
+        match server.start_workers(0, |request| {
+            Response::new(request.inner().version(), StatusCode::NoContent)
+        }) {
+            Err(ServerError::IOError(e)) => {
+                assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput)
+            }
+            _ => panic!("Expected an IOError."),
+        }
+
+        // The requests are still handed out by the server.
+        let mut socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        socket
+            .write_all(b"GET /machine-config HTTP/1.1\r\n\r\n")
+            .unwrap();
+        let mut req_vec = vec![];
+        while req_vec.is_empty() {
+            req_vec = server.requests().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_wait_response_sender() {
+        let path_to_socket = get_temp_socket_file();
+        let mut server = HttpServer::new_uds(path_to_socket.as_path()).unwrap();
+        server.start_server().unwrap();
+        let response_sender = server.response_sender();
+
+        // Waking the server up interrupts its wait for events.
+        let waker = response_sender.clone();
+        let waker_thread = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            waker.wake().unwrap();
+        });
+        assert!(server.requests().unwrap().is_empty());
+        waker_thread.join().unwrap();
+
+        // Responses produced on other threads are sent.
+        let mut socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        socket
+            .write_all(b"GET /machine-config HTTP/1.1\r\n\r\n")
+            .unwrap();
+        let mut req_vec = vec![];
+        while req_vec.is_empty() {
+            req_vec = server.requests().unwrap();
+        }
+        let server_request = req_vec.remove(0);
+        let sender = response_sender.clone();
+        let sender_thread = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            let response = server_request
+                .process(|_request| Response::new(Version::Http11, StatusCode::NoContent));
+            sender.send(response).unwrap();
+        });
+        // The server returns to send the response.
+        assert!(server.requests().unwrap().is_empty());
+        sender_thread.join().unwrap();
+        while server
+            .connections
+            .values()
+            .any(|client_connection| client_connection.connection.pending_write())
+        {
+            assert!(server.requests().unwrap().is_empty());
+        }
+        let mut buf = [0u8; 1024];
+        let bytes_read = socket.read(&mut buf[..]).unwrap();
+        assert_eq!(&buf[..bytes_read], b"HTTP/1.1 204 No Content\r\n\r\n");
+
+        // Nothing can be sent once the server is gone.
+        drop(server);
+        let request = Request::try_from(b"GET /machine-config HTTP/1.1\r\n\r\n").unwrap();
+        let response = ServerRequest::new(
+            request,
+            RequestId {
+                fd: 0,
+                generation: 0,
+                sequence: 0,
+            },
+            PeerInfo::Uds(PeerCredentials {
+                pid: 0,
+                uid: 0,
+                gid: 0,
+            }),
+            ListenerId(0),
+        )
+        .process(|_request| Response::new(Version::Http11, StatusCode::NoContent));
+        assert!(matches!(
+            response_sender.send(response),
+            Err(ServerError::ServerGone)
+        ));
+    }
+
+    #[test]
+    fn test_wait_blocked_body_stream() {
+        // A body source which has no data until it is given some.
+        struct Source(std::sync::Arc<std::sync::Mutex<(Vec<u8>, bool)>>);
+
+        impl Read for Source {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                let mut source = self.0.lock().unwrap();
+                if source.0.is_empty() && !source.1 {
+                    return Err(std::io::Error::from(std::io::ErrorKind::WouldBlock));
+                }
+                let bytes_read = std::cmp::min(buf.len(), source.0.len());
+                buf[..bytes_read].copy_from_slice(&source.0[..bytes_read]);
+                source.0.drain(..bytes_read);
+                Ok(bytes_read)
+            }
+        }
+
+        let path_to_socket = get_temp_socket_file();
+        let mut server = HttpServer::new_uds(path_to_socket.as_path()).unwrap();
+        server.start_server().unwrap();
+
+        let mut socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+        socket.set_nonblocking(true).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        socket
+            .write_all(b"GET /machine-config HTTP/1.1\r\n\r\n")
+            .unwrap();
+        let mut req_vec = vec![];
+        while req_vec.is_empty() {
+            req_vec = server.requests().unwrap();
+        }
+        let shared_source = std::sync::Arc::new(std::sync::Mutex::new((Vec::new(), false)));
+        let source = shared_source.clone();
+        server
+            .respond(req_vec.remove(0).process(move |_request| {
+                let mut response = Response::new(Version::Http11, StatusCode::OK);
+                response.with_body_stream(Source(source.clone()));
+                response
+            }))
+            .unwrap();
+
+        let response_sender = server.response_sender();
+        let producer = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(100));
+            shared_source.lock().unwrap().0.extend_from_slice(b"hello");
+            response_sender.wake().unwrap();
+            std::thread::sleep(Duration::from_millis(100));
+            shared_source.lock().unwrap().1 = true;
+            response_sender.wake().unwrap();
+        });
+
+        // The server waits for the source instead of spinning on the writable stream.
+        let mut response_bytes = vec![];
+        let mut requests_calls = 0;
+        let mut buf = [0u8; 1024];
+        while !response_bytes.ends_with(b"0\r\n\r\n") {
+            assert!(server.requests().unwrap().is_empty());
+            requests_calls += 1;
+            if let Ok(bytes_read) = socket.read(&mut buf[..]) {
+                response_bytes.extend_from_slice(&buf[..bytes_read]);
+            }
+        }
+        producer.join().unwrap();
+        assert!(requests_calls < 20);
+        assert_eq!(
+            String::from_utf8(response_bytes).unwrap(),
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn test_wait_pause_accept_multiple_listeners() {
+        let first_path = get_temp_socket_file();
+        let second_path = get_temp_socket_file();
+        let mut config = HttpServerConfig::default();
+        config
+            .with_max_connections(1)
+            .with_overload_policy(OverloadPolicy::PauseAccept);
+        let mut server = HttpServer::new_uds_with_config(first_path.as_path(), config).unwrap();
+        server.add_uds_listener(second_path.as_path()).unwrap();
+        server.start_server().unwrap();
+
+        let first_socket = UnixStream::connect(first_path.as_path()).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        // Both listeners are readable in the same batch of events while the
+        // server is full.
+        let _second_socket = UnixStream::connect(first_path.as_path()).unwrap();
+        let _third_socket = UnixStream::connect(second_path.as_path()).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        assert!(server.accept_paused);
+        assert_eq!(server.metrics().accepted_connections, 1);
+
+        drop(first_socket);
+        while server.metrics().accepted_connections == 1 {
+            assert!(server.requests().unwrap().is_empty());
+        }
+        assert!(server.accept_paused);
+        assert_eq!(server.connections.len(), 1);
+        assert_eq!(server.metrics().rejected_connections, 0);
+    }
+
+    #[test]
+    fn test_wait_evict_idle_stale_events() {
+        let path_to_socket = get_temp_socket_file();
+        let mut config = HttpServerConfig::default();
+        config
+            .with_max_connections(1)
+            .with_overload_policy(OverloadPolicy::EvictIdle);
+        let mut server = HttpServer::new_uds_with_config(path_to_socket.as_path(), config).unwrap();
+        server.start_server().unwrap();
+
+        let mut first_socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        // The event of the evicted connection comes after the one of the
+        // listener in the same batch, so it must not reach the new connection.
+        let mut second_socket = UnixStream::connect(path_to_socket.as_path()).unwrap();
+        first_socket.write_all(b"GET").unwrap();
+        assert!(server.requests().unwrap().is_empty());
+        assert_eq!(server.metrics().evicted_connections, 1);
+        // Nothing was read from the new connection, so it has nothing to answer.
+        assert!(server
+            .connections
+            .values()
+            .all(|client_connection| client_connection.state
+                == ClientConnectionState::AwaitingIncoming));
+
+        second_socket
+            .write_all(b"GET /machine-config HTTP/1.1\r\n\r\n")
+            .unwrap();
+        let mut req_vec = vec![];
+        while req_vec.is_empty() {
+            req_vec = server.requests().unwrap();
+        }
+        assert_eq!(req_vec[0].request.uri().get_abs_path(), "/machine-config");
+    }
+}
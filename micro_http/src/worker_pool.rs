@@ -0,0 +1,115 @@
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{Builder, JoinHandle};
+
+use response::{Response, StatusCode};
+use server::{HttpServerConfig, ResponseSender, ServerRequest};
+
+/// Signature of the function with which the workers answer the requests.
+pub(crate) type WorkerHandler = dyn Fn(&ServerRequest) -> Response + Send + Sync;
+
+/// A fixed set of threads which answer the requests received by a server and
+/// send the responses back to it.
+pub(crate) struct WorkerPool {
+    /// Hands the requests to the workers, which stop once it is dropped and the
+    /// requests handed so far are answered.
+    requests: Option<Sender<ServerRequest>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl WorkerPool {
+    /// Spawns `worker_count` threads which answer requests with `handler` and
+    /// send the responses through `responses`.
+    ///
+    /// A request whose handler panics is answered with `500 Internal Server Error`,
+    /// built from `config` as the other responses the server sends on its own.
+    ///
+    /// An error of kind `InvalidInput` is returned if `worker_count` is zero, as
+    /// the requests would then never be answered.
+    pub fn new(
+        worker_count: usize,
+        handler: Arc<WorkerHandler>,
+        responses: ResponseSender,
+        config: &HttpServerConfig,
+    ) -> std::io::Result<Self> {
+        if worker_count == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "At least one worker is needed.",
+            ));
+        }
+        let (request_sender, request_receiver) = channel::<ServerRequest>();
+        let request_receiver = Arc::new(Mutex::new(request_receiver));
+        let config = Arc::new(config.clone());
+        let mut workers = Vec::with_capacity(worker_count);
+        for index in 0..worker_count {
+            let request_receiver = request_receiver.clone();
+            let handler = handler.clone();
+            let responses = responses.clone();
+            let config = config.clone();
+            workers.push(
+                Builder::new()
+                    .name(format!("micro_http_worker_{}", index))
+                    .spawn(move || work(&request_receiver, &*handler, &responses, &config))?,
+            );
+        }
+        Ok(Self {
+            requests: Some(request_sender),
+            workers,
+        })
+    }
+
+    /// Hands `request` to the first worker which is free.
+    pub fn dispatch(&self, request: ServerRequest) {
+        if let Some(requests) = self.requests.as_ref() {
+            // The workers only stop once `requests` is dropped.
+            let _ = requests.send(request);
+        }
+    }
+}
+
+impl Drop for WorkerPool {
+    fn drop(&mut self) {
+        self.requests.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// The loop run by each worker, which answers requests until there are none
+/// left to receive or the server is gone.
+fn work(
+    requests: &Mutex<Receiver<ServerRequest>>,
+    handler: &WorkerHandler,
+    responses: &ResponseSender,
+    config: &HttpServerConfig,
+) {
+    loop {
+        // The lock is released before the request is handled, so that the other
+        // workers can receive requests in the meantime.
+        let request = match requests.lock() {
+            Ok(requests) => requests.recv(),
+            Err(_) => return,
+        };
+        let request = match request {
+            Ok(request) => request,
+            Err(_) => return,
+        };
+        let response = catch_unwind(AssertUnwindSafe(|| {
+            request.process(|_request| handler(&request))
+        }))
+        .unwrap_or_else(|_| {
+            request.process(|_request| {
+                config.error_response(
+                    StatusCode::InternalServerError,
+                    "The request handler failed.",
+                )
+            })
+        });
+        if responses.send(response).is_err() {
+            return;
+        }
+    }
+}